@@ -0,0 +1,143 @@
+use crate::opcode::Instruction;
+use crate::platform::Platform;
+
+const LOAD_STORE_LOOKAHEAD: usize = 16; //instructions to follow after Fx55/Fx65 looking for a use of I
+
+/// Code patterns whose behavior differs between interpreters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuirkHint {
+    Shift,      //8xy6/8xyE with two different registers
+    LoadStore,  //I is used straight after Fx55/Fx65 without being set again
+    Jump,       //Bnnn
+    KeyWait     //Fx0A
+}
+
+impl QuirkHint {
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            QuirkHint::Shift => "shift: 8xy6/8xyE with Vx != Vy, depends on whether Vy or Vx is shifted",
+            QuirkHint::LoadStore => "load/store: uses I after Fx55/Fx65, depends on whether they increment I",
+            QuirkHint::Jump => "jump: Bnnn, depends on whether it adds V0 or Vx",
+            QuirkHint::KeyWait => "key wait: Fx0A, depends on whether it completes on press or release"
+        }
+    }
+}
+
+/// Result of scanning a ROM's reachable code.
+pub struct Analysis {
+    pub platform: Platform,
+    pub extensions: Vec<(u16, Instruction)>, //instructions from a later platform than CHIP-8, with their address
+    pub hints: Vec<QuirkHint>,
+    pub code: Vec<u16>,       //addresses of the instructions found
+    pub computed_jumps: usize //Bnnn jumps, code past them can't be followed
+}
+
+impl Analysis {
+
+    pub fn print_report(&self) {
+        println!("Platform: {} ({} reachable instructions)", self.platform.get_name(), self.code.len());
+        for (address, instruction) in self.extensions.iter() {
+            println!("  {:03X}: {} ({})", address, instruction, instruction.get_platform().get_name());
+        }
+        if self.computed_jumps > 0 {
+            println!("  {} computed jump(s), code they reach was not scanned", self.computed_jumps);
+        }
+        for hint in self.hints.iter() {
+            println!("Quirk {}", hint.get_description());
+        }
+    }
+
+}
+
+/// Walk the code reachable from `program_start`, following jumps, calls and both sides of skips.
+pub fn analyze(rom: &[u8], program_start: u16) -> Analysis {
+
+    let fetch = |address: u16| -> Option<Instruction> {
+        let offset = address.checked_sub(program_start)? as usize;
+        let high = *rom.get(offset)?;
+        let low = *rom.get(offset + 1)?;
+        Some(Instruction::decode((high as u16) << 8 | low as u16))
+    };
+
+    let mut visited = vec![false; 0x10000];
+    let mut queue = vec![program_start];
+    let mut analysis = Analysis {
+        platform: Platform::Chip8,
+        extensions: Vec::new(),
+        hints: Vec::new(),
+        code: Vec::new(),
+        computed_jumps: 0
+    };
+
+    while let Some(address) = queue.pop() {
+
+        if visited[address as usize] {
+            continue;
+        }
+        let instruction = match fetch(address) {
+            Some(Instruction::Invalid(_)) | None => continue, //ran into data, or off the end of the ROM
+            Some(instruction) => instruction
+        };
+        visited[address as usize] = true;
+        analysis.code.push(address);
+
+        if instruction.get_platform() != Platform::Chip8 {
+            analysis.platform = analysis.platform.max(instruction.get_platform());
+            analysis.extensions.push((address, instruction));
+        }
+
+        let next = address.wrapping_add(instruction.get_length());
+        match instruction {
+            Instruction::Jp(target) => queue.push(target),
+            Instruction::Call(target) => {
+                queue.push(target);
+                queue.push(next);
+            }
+            Instruction::Ret | Instruction::Exit => {}
+            Instruction::JpV0(_) => {
+                analysis.computed_jumps += 1;
+                add_hint(&mut analysis.hints, QuirkHint::Jump);
+            }
+            Instruction::SeByte(..) | Instruction::SneByte(..) | Instruction::SeReg(..) | Instruction::SneReg(..) |
+            Instruction::Skp(_) | Instruction::Sknp(_) => {
+                queue.push(next);
+                //XO-CHIP skips the whole of a 4 byte instruction
+                let skipped_length = fetch(next).map(|skipped| skipped.get_length()).unwrap_or(2);
+                queue.push(next.wrapping_add(skipped_length));
+            }
+            _ => queue.push(next)
+        }
+
+        match instruction {
+            Instruction::Shr(x, y) | Instruction::Shl(x, y) if x != y => add_hint(&mut analysis.hints, QuirkHint::Shift),
+            Instruction::Store(_) | Instruction::Load(_) if uses_i_after(&fetch, next) => add_hint(&mut analysis.hints, QuirkHint::LoadStore),
+            Instruction::LdVxK(_) => add_hint(&mut analysis.hints, QuirkHint::KeyWait),
+            _ => {}
+        }
+    }
+
+    analysis.code.sort_unstable();
+    analysis.extensions.sort_by_key(|(address, _)| *address);
+    analysis
+}
+
+fn add_hint(hints: &mut Vec<QuirkHint>, hint: QuirkHint) {
+    if !hints.contains(&hint) {
+        hints.push(hint);
+    }
+}
+
+//follow straight-line code for a read of I before anything sets it again
+fn uses_i_after(fetch: &impl Fn(u16) -> Option<Instruction>, mut address: u16) -> bool {
+    for _ in 0..LOAD_STORE_LOOKAHEAD {
+        match fetch(address) {
+            Some(Instruction::Drw(..)) | Some(Instruction::LdB(_)) | Some(Instruction::Store(_)) | Some(Instruction::Load(_)) |
+            Some(Instruction::AddI(_)) | Some(Instruction::StoreRange(..)) | Some(Instruction::LoadRange(..)) => return true,
+            Some(Instruction::LdI(_)) | Some(Instruction::LdF(_)) | Some(Instruction::LdHf(_)) | Some(Instruction::LdILong) => return false,
+            Some(Instruction::Jp(_)) | Some(Instruction::JpV0(_)) | Some(Instruction::Call(_)) | Some(Instruction::Ret) |
+            Some(Instruction::Exit) | Some(Instruction::Invalid(_)) | None => return false,
+            Some(instruction) => address = address.wrapping_add(instruction.get_length())
+        }
+    }
+    false
+}
@@ -0,0 +1,241 @@
+//! Tools for execution traces written with `c8emu --trace`.
+//!
+//! `c8trace diff <a> <b> [--context n]` finds the first instruction where two traces disagree.
+//! Either trace can also be a text log from Octo or another emulator, one instruction per line.
+//! `c8trace dump <trace>` prints a trace as text.
+
+use std::env;
+use std::fs;
+use std::process;
+
+#[path = "../trace.rs"]
+#[allow(dead_code)] //the emulator writes traces, this only reads them
+mod trace;
+
+use trace::{RECORD_SIZE, TRACE_MAGIC, TRACE_VERSION};
+
+const USAGE: &str = "Usage: c8trace diff <trace> <trace> [--context <n>]\n       c8trace dump <trace>";
+const DEFAULT_CONTEXT: usize = 5;
+
+/// The cpu state before one instruction ran. Text logs don't always have every field,
+/// only fields both traces have are compared.
+#[derive(Clone, Default)]
+struct Step {
+    cycle: Option<u64>,
+    pc: Option<u16>,
+    opcode: Option<u16>,
+    i: Option<u16>,
+    sp: Option<u8>,
+    reg: [Option<u8>; 16]
+}
+
+impl Step {
+
+    fn from_bytes(record: &[u8]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([record[offset], record[offset + 1]]);
+        let mut cycle = [0; 8];
+        cycle.copy_from_slice(&record[0..8]);
+        let mut reg = [None; 16];
+        for (value, byte) in reg.iter_mut().zip(record[15..31].iter()) {
+            *value = Some(*byte);
+        }
+        Self {
+            cycle: Some(u64::from_le_bytes(cycle)),
+            pc: Some(u16_at(8)),
+            opcode: Some(u16_at(10)),
+            i: Some(u16_at(12)),
+            sp: Some(record[14]),
+            reg
+        }
+    }
+
+    /// Read `KEY:value` or `KEY=value` fields, e.g. `PC:0200 OP:6A02 V0:00 ... I:0000 SP:0`.
+    /// Lines with no named fields are read as an address followed by an opcode, like disassembly logs.
+    /// Values are hex, except `cycle` which is decimal. Lines without a PC are skipped.
+    fn parse_line(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            return None;
+        }
+
+        let mut step = Step::default();
+        let mut named = false;
+        let tokens: Vec<&str> = line.split(|c: char| c.is_whitespace() || c == ',').filter(|token| !token.is_empty()).collect();
+        for token in tokens.iter() {
+            let (key, value) = match token.split_once([':', '=']) {
+                Some((key, value)) if !value.is_empty() => (key.to_ascii_lowercase(), value),
+                _ => continue
+            };
+            named = true;
+            match key.as_str() {
+                "pc" => step.pc = parse_hex(value),
+                "op" | "opcode" => step.opcode = parse_hex(value),
+                "i" => step.i = parse_hex(value),
+                "sp" => step.sp = parse_hex(value).map(|sp| sp as u8),
+                "cycle" | "cycles" => step.cycle = value.parse().ok(),
+                _ => {
+                    let register = key.strip_prefix('v').and_then(|index| usize::from_str_radix(index, 16).ok());
+                    if let Some(index) = register.filter(|index| *index < 16) {
+                        step.reg[index] = parse_hex(value).map(|value| value as u8);
+                    }
+                }
+            }
+        }
+
+        if !named {
+            step.pc = tokens.first().and_then(|token| parse_hex(token.trim_end_matches(':'))); //`0200: 6A02`
+            step.opcode = tokens.get(1).and_then(|token| parse_hex(token));
+        }
+        step.pc?;
+        Some(step)
+    }
+
+    //names and values of the fields that differ
+    fn compare(&self, other: &Step) -> Vec<(String, String, String)> {
+        let mut differences = Vec::new();
+        let mut check = |name: String, a: Option<u16>, b: Option<u16>, width: usize| {
+            if let (Some(a), Some(b)) = (a, b) {
+                if a != b {
+                    differences.push((name, format!("{:0w$X}", a, w = width), format!("{:0w$X}", b, w = width)));
+                }
+            }
+        };
+        check("PC".to_string(), self.pc, other.pc, 3);
+        check("OP".to_string(), self.opcode, other.opcode, 4);
+        check("I".to_string(), self.i, other.i, 3);
+        check("SP".to_string(), self.sp.map(u16::from), other.sp.map(u16::from), 1);
+        for index in 0..16 {
+            check(format!("V{:X}", index), self.reg[index].map(u16::from), other.reg[index].map(u16::from), 2);
+        }
+        differences
+    }
+
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let field = |value: Option<u16>, width: usize| value.map(|value| format!("{:0w$X}", value, w = width)).unwrap_or_else(|| "-".repeat(width));
+        write!(f, "PC:{} OP:{} I:{} SP:{}", field(self.pc, 3), field(self.opcode, 4), field(self.i, 3), field(self.sp.map(u16::from), 1))?;
+        for (index, value) in self.reg.iter().enumerate() {
+            write!(f, " V{:X}:{}", index, field(value.map(u16::from), 2))?;
+        }
+        if let Some(cycle) = self.cycle {
+            write!(f, " cycle:{}", cycle)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_hex(value: &str) -> Option<u16> {
+    let digits = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")).or_else(|| value.strip_prefix('$')).unwrap_or(value);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Load a binary trace, or a text log if the file doesn't start with the trace header.
+fn load(filename: &str) -> Vec<Step> {
+    let data = fs::read(filename).unwrap_or_else(|e| {
+        println!("Error reading {}: {}", filename, e);
+        process::exit(2);
+    });
+
+    if data.starts_with(TRACE_MAGIC) {
+        if data.get(TRACE_MAGIC.len()) != Some(&TRACE_VERSION) {
+            println!("Error reading {}: unsupported trace version", filename);
+            process::exit(2);
+        }
+        data[TRACE_MAGIC.len() + 1..].chunks_exact(RECORD_SIZE).map(Step::from_bytes).collect()
+    } else {
+        String::from_utf8_lossy(&data).lines().filter_map(Step::parse_line).collect()
+    }
+}
+
+fn diff(a_filename: &str, b_filename: &str, context: usize) -> bool {
+    let a = load(a_filename);
+    let b = load(b_filename);
+    println!("{}: {} instructions", a_filename, a.len());
+    println!("{}: {} instructions", b_filename, b.len());
+
+    let divergence = a.iter().zip(b.iter()).position(|(a, b)| !a.compare(b).is_empty());
+    let index = match divergence {
+        Some(index) => index,
+        None if a.len() == b.len() => {
+            println!("Traces match");
+            return true;
+        }
+        None => {
+            let (longer, shorter) = if a.len() > b.len() { (a_filename, b_filename) } else { (b_filename, a_filename) };
+            println!("Traces match until {} ends, {} continues", shorter, longer);
+            return false;
+        }
+    };
+
+    println!("First divergence at instruction {}:", index);
+    for (name, a_value, b_value) in a[index].compare(&b[index]) {
+        println!("  {:<3} {} vs {}", name, a_value, b_value);
+    }
+    println!();
+    for (line, step) in a.iter().enumerate().take(index).skip(index.saturating_sub(context)) {
+        println!("   {:>8}  {}", line, step);
+    }
+    println!("a  {:>8}  {}", index, a[index]);
+    println!("b  {:>8}  {}", index, b[index]);
+    false
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+        ["diff", a, b] => process::exit(if diff(a, b, DEFAULT_CONTEXT) { 0 } else { 1 }),
+        ["diff", a, b, "--context", n] => {
+            let context = n.parse().unwrap_or_else(|_| {
+                println!("{}", USAGE);
+                process::exit(2);
+            });
+            process::exit(if diff(a, b, context) { 0 } else { 1 })
+        }
+        ["dump", filename] => {
+            for (index, step) in load(filename).iter().enumerate() {
+                println!("{:>8}  {}", index, step);
+            }
+        }
+        _ => {
+            println!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_fields_are_read() {
+        let step = Step::parse_line("PC:0200 OP:6A02 V0:00 VA:1F I=0x300 SP:2 cycle:15").expect("line was skipped");
+        assert_eq!(step.pc, Some(0x200));
+        assert_eq!(step.opcode, Some(0x6A02));
+        assert_eq!(step.reg[0x0], Some(0x00));
+        assert_eq!(step.reg[0xA], Some(0x1F));
+        assert_eq!(step.reg[0x1], None);
+        assert_eq!(step.i, Some(0x300));
+        assert_eq!(step.sp, Some(2));
+        assert_eq!(step.cycle, Some(15));
+    }
+
+    #[test]
+    fn positional_address_and_opcode_are_read() {
+        for line in ["0200 6A02", "0200: 6A02", "$200, 0x6A02"] {
+            let step = Step::parse_line(line).unwrap_or_else(|| panic!("{} was skipped", line));
+            assert_eq!(step.pc, Some(0x200), "{}", line);
+            assert_eq!(step.opcode, Some(0x6A02), "{}", line);
+        }
+    }
+
+    #[test]
+    fn comments_and_lines_without_pc_are_skipped() {
+        assert!(Step::parse_line("# header").is_none());
+        assert!(Step::parse_line("; comment").is_none());
+        assert!(Step::parse_line("").is_none());
+        assert!(Step::parse_line("OP:6A02 V0:00").is_none());
+    }
+}
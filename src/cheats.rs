@@ -0,0 +1,165 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::memory::Memory;
+
+const CHEATS_DIR: &str = "cheats";
+
+/// How a RAM search narrows down its candidates, comparing each address
+/// with a given value, or with its value at the previous search step if none is given.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchFilter {
+    Equal,
+    Changed,
+    Increased,
+    Decreased
+}
+
+impl SearchFilter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "equal" => Some(Self::Equal),
+            "changed" => Some(Self::Changed),
+            "increased" => Some(Self::Increased),
+            "decreased" => Some(Self::Decreased),
+            _ => None
+        }
+    }
+}
+
+/// RAM search for finding variables like score and lives.
+///
+/// Start a search, play until the variable changes, filter, and repeat until few addresses are left.
+pub struct RamSearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>
+}
+
+impl RamSearch {
+
+    pub fn new() -> Self {
+        Self {
+            snapshot: Vec::new(),
+            candidates: Vec::new()
+        }
+    }
+
+    /// Snapshot memory with every address as a candidate.
+    pub fn start(&mut self, memory: &Memory) {
+        self.snapshot = memory.get_all().to_vec();
+        self.candidates = (0..self.snapshot.len() as u16).collect();
+    }
+
+    /// Keep the candidates that pass the filter, then take a new snapshot to compare against next time.
+    pub fn filter(&mut self, memory: &Memory, filter: SearchFilter, value: Option<u8>) {
+        let current = memory.get_all();
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|address| {
+            let now = current[*address as usize];
+            let then = value.unwrap_or(snapshot[*address as usize]);
+            match filter {
+                SearchFilter::Equal => now == then,
+                SearchFilter::Changed => now != then,
+                SearchFilter::Increased => now > then,
+                SearchFilter::Decreased => now < then
+            }
+        });
+        self.snapshot = current.to_vec();
+    }
+
+    /// Remaining addresses with their current values.
+    pub fn get_results(&self, memory: &Memory) -> Vec<(u16, u8)> {
+        self.candidates.iter().map(|address| (*address, memory.get_memory(*address))).collect()
+    }
+
+}
+
+/// An address frozen at a value.
+#[derive(Clone, Debug)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    pub enabled: bool,
+    pub name: String
+}
+
+/// Cheats for one ROM, written back to every frame.
+///
+/// Kept in `cheats/<rom hash>.txt`, one cheat per line: `<address> <value> <on|off> [name]`
+/// with the address and value in hex. Lines starting with `#` are ignored.
+pub struct CheatList {
+    cheats: Vec<Cheat>,
+    filename: String
+}
+
+impl CheatList {
+
+    /// Load the cheats for a ROM, an empty list if it has none.
+    pub fn load(rom_hash: u64) -> Self {
+        let filename = Path::new(CHEATS_DIR).join(format!("{:016x}.txt", rom_hash)).to_string_lossy().to_string();
+        let cheats = match fs::read_to_string(&filename) {
+            Ok(text) => text.lines().filter_map(Self::parse_line).collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                println!("Error reading {}: {}", filename, e);
+                Vec::new()
+            }
+        };
+        Self { cheats, filename }
+    }
+
+    fn parse_line(line: &str) -> Option<Cheat> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let mut fields = line.splitn(4, ' ');
+        let cheat = Cheat {
+            address: u16::from_str_radix(fields.next()?, 16).ok()?,
+            value: u8::from_str_radix(fields.next()?, 16).ok()?,
+            enabled: fields.next()? == "on",
+            name: fields.next().unwrap_or("").to_string()
+        };
+        Some(cheat)
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        fs::create_dir_all(CHEATS_DIR)?;
+        let text: String = self.cheats.iter()
+            .map(|cheat| format!("{:03x} {:02x} {} {}\n", cheat.address, cheat.value, if cheat.enabled { "on" } else { "off" }, cheat.name))
+            .collect();
+        fs::write(&self.filename, text)
+    }
+
+    pub fn get_cheats(&self) -> &[Cheat] { &self.cheats }
+
+    pub fn add(&mut self, cheat: Cheat) -> std::io::Result<()> {
+        self.cheats.push(cheat);
+        self.save()
+    }
+
+    pub fn remove(&mut self, index: usize) -> std::io::Result<()> {
+        if index < self.cheats.len() {
+            self.cheats.remove(index);
+        }
+        self.save()
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> std::io::Result<()> {
+        if let Some(cheat) = self.cheats.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+        self.save()
+    }
+
+    /// Write every enabled cheat's value into memory.
+    pub fn apply(&self, memory: &mut Memory) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            if (cheat.address as usize) < memory.get_size() {
+                memory.set_memory(cheat.address, cheat.value);
+            }
+        }
+    }
+
+}
@@ -0,0 +1,338 @@
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::display_module::DisplayModule;
+use crate::timer_module::TimerModule;
+use crate::keyboard_module::KeyboardModule;
+use crate::save_state::{slot_filename, StateReader, StateWriter};
+use crate::rom_watcher::WatchMode;
+use crate::cheats::{CheatList, RamSearch};
+use crate::symbols::SymbolTable;
+use crate::platform::Platform;
+
+/// The whole machine: cpu plus the modules it talks to.
+///
+/// Components are public so the front end can reach into them (drawing, input, debugging),
+/// anything that touches several of them at once lives here.
+pub struct Chip8 {
+    pub cpu: Cpu,
+    pub memory: Memory,
+    pub display_module: DisplayModule,
+    pub timer_module: TimerModule,
+    pub keyboard_module: KeyboardModule,
+    pub ram_search: RamSearch,
+    pub cheats: CheatList,
+    pub symbols: SymbolTable,
+    rom_filename: String,
+    display_memory: Vec<u8> //display bytes as of the last sync, when the display is kept in memory
+}
+
+impl Chip8 {
+
+    pub fn new(cpu: Cpu, display_module: DisplayModule, rom_filename: &str) -> Self {
+        let mut memory = Memory::new();
+        memory.initialize(rom_filename);
+        Self::with_memory(cpu, display_module, memory, rom_filename)
+    }
+
+    /// A plain CHIP-8 running the given ROM bytes, for tests that don't need a ROM file.
+    #[cfg(test)]
+    pub fn for_test(rom: &[u8]) -> Self {
+        let mut memory = Memory::new();
+        memory.load_rom(rom.to_vec());
+        let cpu = Cpu::new(crate::prng::Prng::new(crate::prng::RngMode::Xorshift, 1), crate::quirks::Quirks::default());
+        Self::with_memory(cpu, DisplayModule::new(64, 32), memory, "")
+    }
+
+    fn with_memory(cpu: Cpu, display_module: DisplayModule, memory: Memory, rom_filename: &str) -> Self {
+        let cheats = CheatList::load(memory.get_rom_hash());
+
+        Self {
+            cpu,
+            memory,
+            display_module,
+            timer_module: TimerModule::new(),
+            keyboard_module: KeyboardModule::new(),
+            ram_search: RamSearch::new(),
+            cheats,
+            symbols: SymbolTable::new(),
+            rom_filename: rom_filename.to_string(),
+            display_memory: Vec::new()
+        }
+    }
+
+    pub fn get_rom_filename(&self) -> &str { &self.rom_filename }
+
+    /// Run as `platform` and hard reset so the ROM is loaded where the platform expects it.
+    /// The display should already be the platform's size.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.cpu.set_platform(platform);
+        self.memory.set_program_start(platform.get_program_start());
+        if platform == Platform::Chip8X {
+            self.display_module.enable_color();
+        }
+        self.reset();
+    }
+
+    /// Execute one cpu cycle.
+    pub fn step(&mut self) {
+        if let Err(fault) = self.cpu.execute_instruction(&mut self.memory, &mut self.display_module, &mut self.timer_module, &mut self.keyboard_module) {
+            println!("Cpu fault: {}, halted until reset", fault);
+            print!("{}", self.get_backtrace());
+        }
+        self.sync_display_memory();
+    }
+
+    //with the display kept in memory, bytes the program wrote there are drawn and pixels drawn are written there
+    fn sync_display_memory(&mut self) {
+        let address = match self.memory.get_map().display_address {
+            Some(address) => address as usize,
+            None => return
+        };
+        let bytes_per_row = self.display_module.get_width() as usize / 8;
+        let size = bytes_per_row * self.display_module.get_height() as usize;
+        if self.display_memory.len() != size {
+            //first sync, or after loading a state where memory and display already agree
+            self.display_memory = self.memory.get_all()[address..address + size].to_vec();
+        }
+
+        //most instructions touch neither, skip the scan for them
+        let program_wrote = self.memory.take_display_written();
+        let display_changed = self.display_module.take_changed();
+        if !program_wrote && !display_changed {
+            return;
+        }
+
+        for offset in 0..size {
+            let (x, y) = (offset % bytes_per_row * 8, offset / bytes_per_row);
+            let stored = self.memory.get_memory((address + offset) as u16);
+            if stored != self.display_memory[offset] {
+                for bit in 0..8 {
+                    self.display_module.set_pixel(x + bit, y, (stored >> (7 - bit)) & 1 != 0);
+                }
+                self.display_memory[offset] = stored;
+            } else {
+                let shown = (0..8).fold(0, |byte, bit| byte << 1 | self.display_module.get_pixel(x + bit, y) as u8);
+                if shown != stored {
+                    self.memory.store_display((address + offset) as u16, shown);
+                    self.display_memory[offset] = shown;
+                }
+            }
+        }
+    }
+
+    /// PC and the call sites of the routines on the stack, innermost first.
+    pub fn get_call_frames(&self) -> Vec<u16> {
+        std::iter::once(self.cpu.get_pc())
+            .chain(self.cpu.get_call_stack().into_iter().map(|return_address| return_address.wrapping_sub(2)))
+            .collect()
+    }
+
+    /// `get_call_frames` one per line, named from the symbol table.
+    pub fn get_backtrace(&self) -> String {
+        self.get_call_frames().into_iter().enumerate()
+            .map(|(n, address)| {
+                if self.symbols.is_empty() {
+                    format!("#{} {:03X}\n", n, address)
+                } else {
+                    format!("#{} {:03X} {}\n", n, address, self.symbols.resolve(address))
+                }
+            })
+            .collect()
+    }
+
+    /// 60Hz housekeeping, run after each frame's batch of cycles.
+    pub fn tick_frame(&mut self) {
+        self.timer_module.update();
+        self.cpu.tick_frame();
+        self.cheats.apply(&mut self.memory);
+    }
+
+    /// Hard reset: memory goes back to the font and the ROM image loaded at startup,
+    /// the cpu, display and timers are cleared and the RNG is reseeded.
+    pub fn reset(&mut self) {
+        self.memory.reset();
+        self.soft_reset();
+    }
+
+    /// Soft reset: like pressing reset on the VIP, the cpu restarts at the program start but memory is left as it is.
+    pub fn soft_reset(&mut self) {
+        self.cpu.reset();
+        self.display_module.clear();
+        self.timer_module.reset();
+    }
+
+    /// Read the ROM from disk again and hard reset, for iterating on a ROM under development.
+    pub fn reload_rom(&mut self) -> std::io::Result<()> {
+        let filename = self.rom_filename.clone();
+        self.load_rom(&filename)
+    }
+
+    /// Switch to a different ROM and hard reset. The running ROM is kept if the file can't be read.
+    pub fn load_rom(&mut self, filename: &str) -> std::io::Result<()> {
+        let rom = Memory::read_rom(filename)?;
+        self.rom_filename = filename.to_string();
+        self.memory.load_rom(rom);
+        self.cheats = CheatList::load(self.memory.get_rom_hash());
+        self.soft_reset();
+        Ok(())
+    }
+
+    /// Pick up a ROM that changed on disk.
+    ///
+    /// With `WatchMode::RestoreState` the slot is loaded even though it was saved with the old ROM,
+    /// then the new ROM is copied over it so the restored program runs the new code.
+    pub fn hot_reload(&mut self, mode: WatchMode) -> std::io::Result<()> {
+        let rom = Memory::read_rom(&self.rom_filename)?;
+        self.memory.replace_rom(rom);
+        self.cheats = CheatList::load(self.memory.get_rom_hash());
+        match mode {
+            WatchMode::Reset => self.reset(),
+            WatchMode::KeepRegisters => self.memory.restore_rom(), //the rest of RAM is left as the program had it
+            WatchMode::RestoreState(slot) => {
+                self.reset();
+                let mut reader = StateReader::read_file(&slot_filename(&self.rom_filename, slot), None, self.cpu.get_platform())?;
+                self.read_state(&mut reader)?;
+                self.memory.restore_rom();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save_state(&self, filename: &str) -> std::io::Result<()> {
+        let mut writer = StateWriter::new(self.memory.get_rom_hash(), self.cpu.get_platform());
+        self.cpu.save_state(&mut writer);
+        self.memory.save_state(&mut writer);
+        self.display_module.save_state(&mut writer);
+        self.timer_module.save_state(&mut writer);
+        writer.write_file(filename)
+    }
+
+    pub fn load_state(&mut self, filename: &str) -> std::io::Result<()> {
+        let mut reader = StateReader::read_file(filename, Some(self.memory.get_rom_hash()), self.cpu.get_platform())?;
+        self.read_state(&mut reader)
+    }
+
+    //everything is read into copies first, so a truncated state leaves the machine as it was
+    fn read_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        let cpu_state = Cpu::read_state(reader)?;
+        let mut memory = self.memory.clone();
+        memory.load_state(reader)?;
+        let mut display_module = self.display_module.clone();
+        display_module.load_state(reader)?;
+        let mut timer_module = self.timer_module.clone();
+        timer_module.load_state(reader)?;
+
+        self.cpu.apply_state(cpu_state);
+        self.memory = memory;
+        self.display_memory.clear();
+        self.display_module = display_module;
+        self.timer_module = timer_module;
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_map::MemoryMap;
+    use crate::prng::{Prng, RngMode};
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn display_in_memory_syncs_both_ways() {
+        let mut chip8 = Chip8::for_test(&[
+            0xA2, 0x0C, //LD I, 20C
+            0xD0, 0x01, //DRW V0, V0, 1
+            0xAF, 0x01, //LD I, F01
+            0x60, 0xFF, //LD V0, FF
+            0xF0, 0x55, //LD [I], V0
+            0x12, 0x0A, //JP 20A
+            0x80        //sprite, leftmost pixel
+        ]);
+        chip8.memory.set_map(MemoryMap::vip(false, true));
+        chip8.memory.set_write_log(true);
+
+        chip8.step();
+        chip8.step();
+        assert_eq!(chip8.memory.get_memory(0xF00), 0x80);
+        assert!(chip8.memory.take_writes().is_empty()); //mirroring the screen isn't a program write
+
+        for _ in 0..3 {
+            chip8.step();
+        }
+        assert!((8..16).all(|x| chip8.display_module.get_pixel(x, 0)));
+        assert!(!chip8.display_module.get_pixel(16, 0));
+    }
+
+    #[test]
+    fn state_from_another_platform_is_rejected() {
+        let state_filename = std::env::temp_dir().join(format!("c8emu-chip8-platform-{}.state", std::process::id())).to_string_lossy().to_string();
+        let chip8_machine = Chip8::for_test(&[0x12, 0x00]);
+        chip8_machine.save_state(&state_filename).expect("Error saving test state");
+
+        let mut color_machine = Chip8::for_test(&[0x12, 0x00]);
+        color_machine.set_platform(Platform::Chip8X);
+        let error = color_machine.load_state(&state_filename).expect_err("state from CHIP-8 loaded on CHIP-8X");
+        std::fs::remove_file(&state_filename).ok();
+        assert!(error.to_string().contains("CHIP-8,"));
+    }
+
+    #[test]
+    fn truncated_state_leaves_machine_alone() {
+        let state_filename = std::env::temp_dir().join(format!("c8emu-chip8-truncated-{}.state", std::process::id())).to_string_lossy().to_string();
+        let mut chip8 = Chip8::for_test(&[0x60, 0x42, 0x12, 0x02]); //LD V0, 42 then JP 202
+        chip8.save_state(&state_filename).expect("Error saving test state");
+        let data = std::fs::read(&state_filename).expect("Error reading test state");
+        std::fs::write(&state_filename, &data[..data.len() - 1]).expect("Error writing test state");
+
+        chip8.step();
+        chip8.memory.set_memory(0x300, 0x99);
+        assert!(chip8.load_state(&state_filename).is_err());
+        std::fs::remove_file(&state_filename).ok();
+        assert_eq!(chip8.cpu.get_reg(0), 0x42);
+        assert_eq!(chip8.cpu.get_pc(), 0x202);
+        assert_eq!(chip8.memory.get_memory(0x300), 0x99);
+    }
+
+    #[test]
+    fn chip8x_programs_load_and_start_at_300() {
+        let mut chip8 = Chip8::for_test(&[0x12, 0x00]); //JP 200, a jump to where CHIP-8X has no code
+        chip8.set_platform(Platform::Chip8X);
+        assert_eq!(chip8.memory.get_memory(0x200), 0);
+        assert_eq!(chip8.memory.get_memory(0x300), 0x12);
+        assert_eq!(chip8.cpu.get_pc(), 0x300);
+    }
+
+    #[test]
+    fn chip8e_store_past_the_end_of_memory_faults() {
+        let mut chip8 = Chip8::for_test(&[
+            0xAF, 0xFE, //LD I, FFE
+            0x50, 0x52  //LD [I], V0-V5, runs 4 bytes past the end
+        ]);
+        chip8.set_platform(Platform::Chip8E);
+        chip8.step();
+        chip8.step();
+        assert_eq!(chip8.cpu.get_fault(), Some(crate::cpu::Fault::IOutOfRange));
+        assert_eq!(chip8.cpu.get_pc(), 0x202);
+        assert_eq!(chip8.memory.get_memory(0xFFF), 0);
+    }
+
+    #[test]
+    fn hot_reload_keeping_registers_leaves_ram_alone() {
+        let rom_filename = std::env::temp_dir().join(format!("c8emu-chip8-hot-reload-{}.ch8", std::process::id())).to_string_lossy().to_string();
+        std::fs::write(&rom_filename, [0x60, 0x42, 0x12, 0x02]).expect("Error writing test ROM"); //LD V0, 42 then JP 202
+        let mut chip8 = Chip8::new(Cpu::new(Prng::new(RngMode::Xorshift, 1), Quirks::default()), DisplayModule::new(64, 32), &rom_filename);
+        chip8.step();
+        chip8.memory.set_memory(0x300, 0x99);
+
+        std::fs::write(&rom_filename, [0x61, 0x17, 0x12, 0x02]).expect("Error writing test ROM"); //LD V1, 17 then JP 202
+        let result = chip8.hot_reload(WatchMode::KeepRegisters);
+        std::fs::remove_file(&rom_filename).ok();
+        result.expect("Error reloading test ROM");
+        assert_eq!(chip8.memory.get_memory(0x200), 0x61);
+        assert_eq!(chip8.memory.get_memory(0x300), 0x99);
+        assert_eq!(chip8.cpu.get_reg(0), 0x42);
+        assert_eq!(chip8.cpu.get_pc(), 0x202);
+    }
+}
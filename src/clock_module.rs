@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+pub const FRAME_RATE: u64 = 60;
+
+//if the host stalls for longer than this many frames, drop the backlog instead of trying to catch up
+const MAX_CATCHUP_FRAMES: u32 = 5;
+
+//frames run per wakeup when fast forwarding with no speed cap
+const UNCAPPED_FRAMES_PER_WAKEUP: u32 = 20;
+
+//slow motion steps, toggled through in order and then back to full speed
+const SLOW_MOTION_SPEEDS: [f64; 2] = [0.5, 0.25];
+
+#[derive(Clone, Copy, Debug)]
+pub enum ClockRate {
+    Hz(u64),                   //cpu instructions per second, spread across 60Hz frames
+    InstructionsPerFrame(u32)  //fixed number of instructions every 60Hz frame
+}
+
+pub struct ClockModule {
+    rate: ClockRate,
+    frame_delay: Duration,
+    last_tick: Instant,
+    accumulator: Duration, //time owed to the emulator that has not yet been spent on whole frames
+    cycle_remainder: u64,  //fractional cycles carried between frames when running in Hz mode (in 1/60ths)
+    turbo_multiplier: u32, //speed while fast forwarding, 0 for uncapped
+    fast_forward: bool,
+    slow_motion: Option<usize> //index into SLOW_MOTION_SPEEDS
+}
+
+impl ClockModule {
+
+    pub fn new(rate: ClockRate, turbo_multiplier: u32) -> Self {
+        Self {
+            rate,
+            frame_delay: Duration::from_nanos(1_000_000_000 / FRAME_RATE),
+            last_tick: Instant::now(),
+            accumulator: Duration::from_secs(0),
+            cycle_remainder: 0,
+            turbo_multiplier,
+            fast_forward: false,
+            slow_motion: None
+        }
+    }
+
+    pub fn set_rate(&mut self, rate: ClockRate) {
+        self.rate = rate;
+        self.cycle_remainder = 0;
+    }
+
+    /// Forget about time that passed while the emulator was paused.
+    pub fn resync(&mut self, now: Instant) {
+        self.last_tick = now;
+        self.accumulator = Duration::from_secs(0);
+    }
+
+    /// Returns how many 60Hz frames are due since the last call.
+    ///
+    /// Leftover time is kept in the accumulator so that the emulator does not drift
+    /// against the wall clock, no matter how often the event loop wakes us up.
+    /// Elapsed time is scaled by the current speed, so the cpu and timers speed up or slow down together.
+    pub fn frames_due(&mut self, now: Instant) -> u32 {
+
+        let elapsed = now.duration_since(self.last_tick);
+        self.last_tick = now;
+
+        if self.is_uncapped() {
+            self.accumulator = Duration::from_secs(0);
+            return UNCAPPED_FRAMES_PER_WAKEUP;
+        }
+
+        self.accumulator += elapsed.mul_f64(self.get_speed());
+
+        let mut frames = 0;
+        while self.accumulator >= self.frame_delay {
+            self.accumulator -= self.frame_delay;
+            frames += 1;
+        }
+
+        let max_frames = MAX_CATCHUP_FRAMES * self.get_speed().ceil() as u32;
+        if frames > max_frames {
+            frames = max_frames; //host was suspended or stalled, don't try to fast forward through it
+            self.accumulator = Duration::from_secs(0);
+        }
+
+        frames
+    }
+
+    /// Returns the number of cpu cycles to run for the next frame.
+    pub fn cycles_for_frame(&mut self) -> u32 {
+        match self.rate {
+            ClockRate::InstructionsPerFrame(ipf) => ipf,
+            ClockRate::Hz(freq) => {
+                //carry the remainder so that e.g. 1000Hz runs 16, 17, 17, 16, ... cycles per frame
+                let total = freq + self.cycle_remainder;
+                self.cycle_remainder = total % FRAME_RATE;
+                (total / FRAME_RATE) as u32
+            }
+        }
+    }
+
+    /// Time at which the next frame is due, used to put the event loop to sleep.
+    pub fn next_frame(&self) -> Instant {
+        if self.is_uncapped() {
+            return self.last_tick;
+        }
+        self.last_tick + (self.frame_delay - self.accumulator).div_f64(self.get_speed())
+    }
+
+    /// Emulated seconds per real second. Fast forward takes priority over slow motion while held.
+    pub fn get_speed(&self) -> f64 {
+        if self.fast_forward && self.turbo_multiplier > 0 {
+            self.turbo_multiplier as f64
+        } else if let Some(index) = self.slow_motion {
+            SLOW_MOTION_SPEEDS[index]
+        } else {
+            1.0
+        }
+    }
+
+    fn is_uncapped(&self) -> bool {
+        self.fast_forward && self.turbo_multiplier == 0
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    /// Step through the slow motion speeds: 1x -> 0.5x -> 0.25x -> 1x
+    pub fn toggle_slow_motion(&mut self) {
+        self.slow_motion = match self.slow_motion {
+            None => Some(0),
+            Some(index) if index + 1 < SLOW_MOTION_SPEEDS.len() => Some(index + 1),
+            Some(_) => None
+        };
+    }
+
+    /// Short description of the current speed for the window title, None at normal speed.
+    pub fn get_speed_label(&self) -> Option<String> {
+        if self.is_uncapped() {
+            Some(String::from("Fast Forward"))
+        } else if self.fast_forward {
+            Some(format!("Fast Forward {}x", self.turbo_multiplier))
+        } else {
+            self.slow_motion.map(|index| format!("Slow Motion {}x", SLOW_MOTION_SPEEDS[index]))
+        }
+    }
+
+}
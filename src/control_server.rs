@@ -0,0 +1,383 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use serde_json::{json, Value};
+
+use crate::cheats::{Cheat, SearchFilter};
+use crate::chip8::Chip8;
+use crate::clock_module::ClockModule;
+use crate::input_source::VirtualDevice;
+use crate::save_state::slot_filename;
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const EMULATOR_ERROR: i64 = -32000;
+
+struct Client {
+    stream: TcpStream,
+    buf: Vec<u8>
+}
+
+/// Local socket API for driving the emulator from scripts.
+///
+/// Speaks JSON-RPC 2.0, one request object per line, e.g.
+/// `{"jsonrpc": "2.0", "id": 1, "method": "step", "params": {"frames": 10}}`.
+/// Methods:
+///   load_rom {path}, run, pause, step {cycles | frames},
+///   set_key {key, pressed}, set_keys {keys: [held keys]},
+///   get_registers, get_call_stack, read_memory {address, length}, write_memory {address, data},
+///   get_framebuffer, save_state {path | slot}, load_state {path | slot},
+///   search_start, search_filter {filter: equal|changed|increased|decreased, value}, search_results,
+///   get_cheats, add_cheat {address, value, name}, remove_cheat {index}, set_cheat_enabled {index, enabled}
+/// `get_call_stack` lists PC and then the call sites, innermost first, like gdb's `monitor backtrace`.
+pub struct ControlServer {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    device: VirtualDevice,
+    paused: bool
+}
+
+impl ControlServer {
+
+    pub fn bind(port: u16) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        println!("Control server listening on 127.0.0.1:{}", port);
+
+        Ok(Self {
+            listener,
+            clients: Vec::new(),
+            device: VirtualDevice::new(),
+            paused: false
+        })
+    }
+
+    /// Keys held through the API, add it to the keyboard module as an input source.
+    pub fn get_device(&self) -> VirtualDevice { self.device.clone() }
+
+    /// True after a `pause` or `step` request, until `run`.
+    pub fn is_paused(&self) -> bool { self.paused }
+
+    /// Accept new clients and answer any complete requests. Returns true if a request was handled,
+    /// so the caller knows the display may need redrawing.
+    pub fn poll(&mut self, chip8: &mut Chip8, clock_module: &mut ClockModule) -> bool {
+
+        while let Ok((stream, addr)) = self.listener.accept() {
+            println!("Control client connected from {}", addr);
+            stream.set_nonblocking(true).ok();
+            stream.set_nodelay(true).ok();
+            self.clients.push(Client { stream, buf: Vec::new() });
+        }
+
+        let mut handled = false;
+        let mut clients = std::mem::take(&mut self.clients);
+        clients.retain_mut(|client| {
+            let connected = read_available(client);
+            while let Some(end) = client.buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = client.buf.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let response = self.handle_request(line.trim(), chip8, clock_module);
+                handled = true;
+                if let Some(response) = response {
+                    if writeln!(client.stream, "{}", response).is_err() {
+                        return false;
+                    }
+                }
+            }
+            if !connected {
+                println!("Control client disconnected");
+            }
+            connected
+        });
+        self.clients = clients;
+
+        handled
+    }
+
+    //returns the response line, or None for a notification (a request without an id)
+    fn handle_request(&mut self, line: &str, chip8: &mut Chip8, clock_module: &mut ClockModule) -> Option<Value> {
+
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, &e.to_string()))
+        };
+        let id = request.get("id").cloned();
+        let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = self.call(method, &params, chip8, clock_module);
+
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => error_response(id, code, &message)
+        })
+    }
+
+    fn call(&mut self, method: &str, params: &Value, chip8: &mut Chip8, clock_module: &mut ClockModule) -> Result<Value, (i64, String)> {
+        match method {
+            "load_rom" => {
+                let path = get_str(params, "path")?;
+                chip8.load_rom(path).map_err(emulator_error)?;
+                Ok(json!(true))
+            }
+            "run" => {
+                self.paused = false;
+                Ok(json!(true))
+            }
+            "pause" => {
+                self.paused = true;
+                Ok(json!(true))
+            }
+            "step" => {
+                self.paused = true;
+                chip8.keyboard_module.poll_sources(&chip8.cpu);
+                if let Some(frames) = params.get("frames") {
+                    let frames = frames.as_u64().ok_or_else(|| invalid_params("frames"))?;
+                    for _ in 0..frames {
+                        for _ in 0..clock_module.cycles_for_frame() {
+                            chip8.step();
+                        }
+                        chip8.tick_frame();
+                    }
+                } else {
+                    let cycles = match params.get("cycles") {
+                        Some(cycles) => cycles.as_u64().ok_or_else(|| invalid_params("cycles"))?,
+                        None => 1
+                    };
+                    for _ in 0..cycles {
+                        chip8.step();
+                    }
+                }
+                Ok(registers(chip8))
+            }
+            "set_key" => {
+                let key = get_key(params.get("key"))?;
+                let pressed = params.get("pressed").and_then(Value::as_bool).ok_or_else(|| invalid_params("pressed"))?;
+                self.device.set_key(key, pressed);
+                chip8.keyboard_module.poll_sources(&chip8.cpu);
+                Ok(json!(true))
+            }
+            "set_keys" => {
+                let keys = params.get("keys").and_then(Value::as_array).ok_or_else(|| invalid_params("keys"))?;
+                let keys = keys.iter().map(|key| get_key(Some(key))).collect::<Result<Vec<u8>, _>>()?;
+                self.device.release_all();
+                for key in keys {
+                    self.device.set_key(key, true);
+                }
+                chip8.keyboard_module.poll_sources(&chip8.cpu);
+                Ok(json!(true))
+            }
+            "get_registers" => Ok(registers(chip8)),
+            "get_call_stack" => {
+                let frames: Vec<Value> = chip8.get_call_frames().iter()
+                    .map(|address| json!({ "address": address, "symbol": chip8.symbols.resolve(*address) }))
+                    .collect();
+                Ok(json!(frames))
+            }
+            "read_memory" => {
+                let (address, length) = (get_u64(params, "address")? as usize, get_u64(params, "length")? as usize);
+                if address.checked_add(length).is_none_or(|end| end > chip8.memory.get_size()) {
+                    return Err(invalid_params("address"));
+                }
+                Ok(json!(chip8.memory.get_all()[address..address + length]))
+            }
+            "write_memory" => {
+                let address = get_u64(params, "address")? as usize;
+                let data = params.get("data").and_then(Value::as_array).ok_or_else(|| invalid_params("data"))?;
+                let data = data.iter()
+                    .map(|byte| byte.as_u64().filter(|byte| *byte <= 0xFF).map(|byte| byte as u8).ok_or_else(|| invalid_params("data")))
+                    .collect::<Result<Vec<u8>, _>>()?;
+                if address.checked_add(data.len()).is_none_or(|end| end > chip8.memory.get_size()) {
+                    return Err(invalid_params("address"));
+                }
+                for (n, byte) in data.iter().enumerate() {
+                    chip8.memory.set_memory((address + n) as u16, *byte);
+                }
+                Ok(json!(true))
+            }
+            "get_framebuffer" => {
+                let display = &chip8.display_module;
+                let rows: Vec<String> = (0..display.get_height() as usize)
+                    .map(|y| (0..display.get_width() as usize).map(|x| if display.get_pixel(x, y) { '1' } else { '0' }).collect())
+                    .collect();
+                Ok(json!({ "width": display.get_width(), "height": display.get_height(), "rows": rows }))
+            }
+            "save_state" => {
+                let filename = state_filename(params, chip8)?;
+                chip8.save_state(&filename).map_err(emulator_error)?;
+                Ok(json!(true))
+            }
+            "load_state" => {
+                let filename = state_filename(params, chip8)?;
+                chip8.load_state(&filename).map_err(emulator_error)?;
+                Ok(json!(true))
+            }
+            "search_start" => {
+                chip8.ram_search.start(&chip8.memory);
+                Ok(json!(true))
+            }
+            "search_filter" => {
+                let filter = SearchFilter::from_name(get_str(params, "filter")?).ok_or_else(|| invalid_params("filter"))?;
+                let value = match params.get("value") {
+                    Some(value) => Some(value.as_u64().filter(|value| *value <= 0xFF).ok_or_else(|| invalid_params("value"))? as u8),
+                    None => None
+                };
+                chip8.ram_search.filter(&chip8.memory, filter, value);
+                Ok(json!(chip8.ram_search.get_results(&chip8.memory).len()))
+            }
+            "search_results" => {
+                let results: Vec<Value> = chip8.ram_search.get_results(&chip8.memory).iter()
+                    .map(|(address, value)| json!({ "address": address, "value": value }))
+                    .collect();
+                Ok(json!(results))
+            }
+            "get_cheats" => {
+                let cheats: Vec<Value> = chip8.cheats.get_cheats().iter()
+                    .map(|cheat| json!({ "address": cheat.address, "value": cheat.value, "enabled": cheat.enabled, "name": cheat.name }))
+                    .collect();
+                Ok(json!(cheats))
+            }
+            "add_cheat" => {
+                let address = get_u64(params, "address")?;
+                let value = get_u64(params, "value")?;
+                if address as usize >= chip8.memory.get_size() {
+                    return Err(invalid_params("address"));
+                }
+                if value > 0xFF {
+                    return Err(invalid_params("value"));
+                }
+                let name = params.get("name").and_then(Value::as_str).unwrap_or("").to_string();
+                chip8.cheats.add(Cheat { address: address as u16, value: value as u8, enabled: true, name }).map_err(emulator_error)?;
+                Ok(json!(true))
+            }
+            "remove_cheat" => {
+                let index = get_u64(params, "index")? as usize;
+                chip8.cheats.remove(index).map_err(emulator_error)?;
+                Ok(json!(true))
+            }
+            "set_cheat_enabled" => {
+                let index = get_u64(params, "index")? as usize;
+                let enabled = params.get("enabled").and_then(Value::as_bool).ok_or_else(|| invalid_params("enabled"))?;
+                chip8.cheats.set_enabled(index, enabled).map_err(emulator_error)?;
+                Ok(json!(true))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method)))
+        }
+    }
+
+}
+
+//read whatever has arrived without blocking, false once the client has gone away
+fn read_available(client: &mut Client) -> bool {
+    let mut read_buf = [0; 1024];
+    loop {
+        match client.stream.read(&mut read_buf) {
+            Ok(0) => return false,
+            Ok(len) => client.buf.extend_from_slice(&read_buf[..len]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+            Err(_) => return false
+        }
+    }
+}
+
+fn registers(chip8: &Chip8) -> Value {
+    let cpu = &chip8.cpu;
+    let v: Vec<u8> = (0..16).map(|x| cpu.get_reg(x)).collect();
+    json!({
+        "v": v,
+        "i": cpu.get_i(),
+        "pc": cpu.get_pc(),
+        "sp": cpu.get_sp(),
+        "fault": cpu.get_fault().map(|fault| fault.to_string()),
+        "delay_timer": chip8.timer_module.get_delay_register(),
+        "sound_timer": chip8.timer_module.get_sound_register(),
+        "cycles": cpu.get_cycles()
+    })
+}
+
+//an explicit path, or a numbered slot next to the ROM like the menu uses
+fn state_filename(params: &Value, chip8: &Chip8) -> Result<String, (i64, String)> {
+    if let Some(path) = params.get("path").and_then(Value::as_str) {
+        return Ok(path.to_string());
+    }
+    let slot = get_u64(params, "slot")?;
+    if slot > u8::MAX as u64 {
+        return Err(invalid_params("slot"));
+    }
+    Ok(slot_filename(chip8.get_rom_filename(), slot as u8))
+}
+
+fn get_str<'a>(params: &'a Value, name: &str) -> Result<&'a str, (i64, String)> {
+    params.get(name).and_then(Value::as_str).ok_or_else(|| invalid_params(name))
+}
+
+fn get_u64(params: &Value, name: &str) -> Result<u64, (i64, String)> {
+    params.get(name).and_then(Value::as_u64).ok_or_else(|| invalid_params(name))
+}
+
+fn get_key(key: Option<&Value>) -> Result<u8, (i64, String)> {
+    key.and_then(Value::as_u64).filter(|key| *key < 16).map(|key| key as u8).ok_or_else(|| invalid_params("key"))
+}
+
+fn invalid_params(name: &str) -> (i64, String) {
+    (INVALID_PARAMS, format!("missing or invalid parameter {}", name))
+}
+
+fn emulator_error(e: std::io::Error) -> (i64, String) {
+    (EMULATOR_ERROR, e.to_string())
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_module::ClockRate;
+
+    struct Machine {
+        server: ControlServer,
+        chip8: Chip8,
+        clock_module: ClockModule
+    }
+
+    impl Machine {
+
+        fn new(rom: &[u8]) -> Self {
+            Self {
+                server: ControlServer::bind(0).expect("Error binding test server"),
+                chip8: Chip8::for_test(rom),
+                clock_module: ClockModule::new(ClockRate::Hz(1000), 4)
+            }
+        }
+
+        fn request(&mut self, line: &str) -> Value {
+            self.server.handle_request(line, &mut self.chip8, &mut self.clock_module).expect("Error, no response")
+        }
+
+    }
+
+    #[test]
+    fn memory_ranges_past_the_end_are_errors() {
+        let mut machine = Machine::new(&[0x12, 0x00]);
+        assert_eq!(machine.request(r#"{"id":1,"method":"read_memory","params":{"address":4094,"length":2}}"#)["result"], json!([0, 0]));
+        assert_eq!(machine.request(r#"{"id":1,"method":"read_memory","params":{"address":4095,"length":2}}"#)["error"]["code"], INVALID_PARAMS);
+        assert_eq!(machine.request(r#"{"id":1,"method":"read_memory","params":{"address":18446744073709551615,"length":1}}"#)["error"]["code"], INVALID_PARAMS);
+        assert_eq!(machine.request(r#"{"id":1,"method":"write_memory","params":{"address":18446744073709551615,"data":[1]}}"#)["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[test]
+    fn call_stack_matches_backtrace() {
+        //200: CALL 204, 202: JP 202, 204: JP 204
+        let mut machine = Machine::new(&[0x22, 0x04, 0x12, 0x02, 0x12, 0x04]);
+        machine.request(r#"{"id":1,"method":"step","params":{"cycles":2}}"#);
+        let frames = machine.request(r#"{"id":1,"method":"get_call_stack"}"#)["result"].clone();
+        assert_eq!(frames, json!([{ "address": 0x204, "symbol": "204" }, { "address": 0x200, "symbol": "200" }]));
+        assert_eq!(machine.chip8.get_backtrace(), "#0 204\n#1 200\n");
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+
+use crate::memory::Memory;
+use crate::opcode::Instruction;
+
+/// Which addresses ran as the start of an instruction, and which way each conditional skip went.
+pub struct Coverage {
+    executed: Vec<u64>,                  //times each address was executed
+    skips: BTreeMap<u16, (u64, u64)>     //skip address to times taken and not taken
+}
+
+impl Coverage {
+
+    pub fn new() -> Self {
+        Self {
+            executed: vec![0; 0x10000],
+            skips: BTreeMap::new()
+        }
+    }
+
+    /// Count an executed instruction, `next_pc` is where the cpu went after it.
+    pub fn record(&mut self, address: u16, opcode: u16, next_pc: u16) {
+        self.executed[address as usize] += 1;
+
+        match Instruction::decode(opcode) {
+            Instruction::SeByte(..) | Instruction::SneByte(..) | Instruction::SeReg(..) | Instruction::SneReg(..) |
+            Instruction::Skp(_) | Instruction::Sknp(_) => {
+                let (taken, not_taken) = self.skips.entry(address).or_insert((0, 0));
+                if next_pc == address.wrapping_add(2) {
+                    *not_taken += 1;
+                } else {
+                    *taken += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Write the report, in lcov's tracefile format if the filename ends in `.info` and as an
+    /// annotated disassembly otherwise. `code` lists instructions that could have run (from the
+    /// analyzer) so code that never did shows up as well.
+    pub fn write_report(&self, filename: &str, rom_filename: &str, memory: &Memory, code: &[u16]) -> std::io::Result<()> {
+
+        let mut addresses: Vec<u16> = code.iter().copied()
+            .chain((0..self.executed.len()).filter(|address| self.executed[*address] > 0).map(|address| address as u16))
+            .collect();
+        addresses.sort_unstable();
+        addresses.dedup();
+
+        let report = if filename.ends_with(".info") {
+            self.lcov(rom_filename, &addresses)
+        } else {
+            self.disassembly(memory, &addresses)
+        };
+        fs::write(filename, report)
+    }
+
+    //one line per address: execution count, address, opcode, mnemonic and skip counts
+    fn disassembly(&self, memory: &Memory, addresses: &[u16]) -> String {
+        let mut report = String::new();
+        let covered = addresses.iter().filter(|address| self.executed[**address as usize] > 0).count();
+        writeln!(report, "; {} of {} instructions executed", covered, addresses.len()).ok();
+
+        for address in addresses {
+            let opcode = fetch(memory, *address);
+            let count = match self.executed[*address as usize] {
+                0 => "#####".to_string(),
+                count => count.to_string()
+            };
+            write!(report, "{:>10}  {:03X}  {:04X}  {:<18}", count, address, opcode, Instruction::decode(opcode).to_string()).ok();
+            if let Some((taken, not_taken)) = self.skips.get(address) {
+                write!(report, "skipped {}, not skipped {}", taken, not_taken).ok();
+            }
+            report.truncate(report.trim_end().len());
+            report.push('\n');
+        }
+        report
+    }
+
+    //addresses stand in for line numbers, each skip is a branch with two outcomes
+    fn lcov(&self, rom_filename: &str, addresses: &[u16]) -> String {
+        let mut report = String::new();
+        writeln!(report, "TN:").ok();
+        writeln!(report, "SF:{}", rom_filename).ok();
+        for address in addresses {
+            if let Some((taken, not_taken)) = self.skips.get(address) {
+                writeln!(report, "BRDA:{},0,0,{}", address, taken).ok();
+                writeln!(report, "BRDA:{},0,1,{}", address, not_taken).ok();
+            }
+        }
+        let branches_hit = self.skips.values().map(|(taken, not_taken)| (*taken > 0) as usize + (*not_taken > 0) as usize).sum::<usize>();
+        writeln!(report, "BRF:{}", self.skips.len() * 2).ok();
+        writeln!(report, "BRH:{}", branches_hit).ok();
+        for address in addresses {
+            writeln!(report, "DA:{},{}", address, self.executed[*address as usize]).ok();
+        }
+        writeln!(report, "LF:{}", addresses.len()).ok();
+        writeln!(report, "LH:{}", addresses.iter().filter(|address| self.executed[**address as usize] > 0).count()).ok();
+        writeln!(report, "end_of_record").ok();
+        report
+    }
+
+}
+
+fn fetch(memory: &Memory, address: u16) -> u16 {
+    let byte = |address: u16| if (address as usize) < memory.get_size() { memory.get_memory(address) } else { 0 };
+    (byte(address) as u16) << 8 | byte(address.wrapping_add(1)) as u16
+}
@@ -1,9 +1,62 @@
+use std::fmt;
+
 use crate::memory::Memory;
+use crate::memory_map::PROGRAM_START;
 use crate::display_module::DisplayModule;
-use rand::Rng;
 use crate::timer_module::TimerModule;
 use crate::keyboard_module::KeyboardModule;
+use crate::prng::{Prng, RngMode};
+use crate::quirks::{KeyWaitQuirk, Quirks};
+use crate::save_state::{StateReader, StateWriter};
+use crate::coverage::Coverage;
+use crate::profiler::Profiler;
+use crate::trace::TraceWriter;
+use crate::sys_call::{self, SysHandler, SysHandlers, SysMode};
+use crate::platform::Platform;
+
+//state of the Fx0A key wait
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum KeyWait {
+    Idle,
+    Press { reg: usize },                 //waiting for any key to go down
+    Release { reg: usize, key: u8 }       //key went down, waiting for it to come back up
+}
 
+/// Errors that stop the cpu, with PC left on the faulting instruction, until it is reset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    StackOverflow,  //CALL with all 16 stack entries in use
+    StackUnderflow, //RET with nothing on the stack
+    PcOutOfRange,   //PC ran off the end of memory
+    IOutOfRange,    //a load or store through I would run off the end of memory
+    MachineCode(u16) //SYS call with no handler, when SYS calls are errors
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::StackOverflow => write!(f, "stack overflow"),
+            Fault::StackUnderflow => write!(f, "stack underflow"),
+            Fault::PcOutOfRange => write!(f, "PC out of range"),
+            Fault::IOutOfRange => write!(f, "I out of range"),
+            Fault::MachineCode(address) => write!(f, "machine code call to {:03X}", address)
+        }
+    }
+}
+
+/// Registers read from a save state, kept aside until the rest of the state has been read as well.
+pub struct CpuState {
+    pc: u16,
+    i: u16,
+    sp: u8,
+    stack: [u16; 16],
+    reg: [u8; 16],
+    key_wait: KeyWait,
+    last_keys: [bool; 16],
+    delay_wait: bool,
+    cycles: u64,
+    rng: Prng
+}
 
 pub struct Cpu {
 
@@ -13,27 +66,64 @@ pub struct Cpu {
     sp: u8,
     stack: [u16; 16],
     reg: [u8; 16],
-    wait: bool,
-    key_reg: u8
+    key_wait: KeyWait,
+    last_keys: [bool; 16], //key state seen on the previous cycle, so Fx0A only reacts to new presses
+    delay_wait: bool,      //CHIP-8E's Fx4F has set the delay timer and is waiting for it to run out
+    quirks: Quirks,
+    platform: Platform,
+
+    rng: Prng,
+    cycles: u64, //number of cycles executed (including ones spent waiting), used to time replayed input
+    coverage: Option<Coverage>,
+    profiler: Option<Profiler>,
+    tracer: Option<TraceWriter>,
+    sys_mode: SysMode,
+    sys_handlers: SysHandlers,
+    fault: Option<Fault>
 }
 
 impl Cpu {
 
-    pub fn new() -> Self {
+    pub fn new(rng: Prng, quirks: Quirks) -> Self {
         Self{
-            pc: 0x200,
+            pc: PROGRAM_START,
             i: 0,
             sp: 0,
             stack: [0;16],
             reg: [0;16],
-            wait: false,
-            key_reg: 0
+            key_wait: KeyWait::Idle,
+            last_keys: [false; 16],
+            delay_wait: false,
+            quirks,
+            platform: Platform::Chip8,
+            rng,
+            cycles: 0,
+            coverage: None,
+            profiler: None,
+            tracer: None,
+            sys_mode: SysMode::Ignore,
+            sys_handlers: SysHandlers::new(),
+            fault: None
         }
     }
 
-    pub fn execute_instruction(&mut self, memory: &mut Memory, display_module: &mut DisplayModule, timer_module: &mut TimerModule, keyboard_module: &mut KeyboardModule) {
+    /// Run one cycle. Returns the fault if this instruction caused one, a faulted cpu does nothing until reset.
+    pub fn execute_instruction(&mut self, memory: &mut Memory, display_module: &mut DisplayModule, timer_module: &mut TimerModule, keyboard_module: &mut KeyboardModule) -> Result<(), Fault> {
+
+        self.cycles += 1;
+        if self.fault.is_some() {
+            return Ok(());
+        }
+
+        let keys = keyboard_module.get_keys();
+        let waiting = self.update_key_wait(&keys);
+        self.last_keys = keys;
+
+        if !waiting {
+            if self.pc as usize + 1 >= memory.get_size() {
+                return self.raise_fault(Fault::PcOutOfRange, self.pc);
+            }
 
-        if !self.wait {
             print!("{:X}", self.pc);
 
             //load instruction from memory (memory[pc] in first byte, memory[pc + 1] in second byte)
@@ -42,13 +132,29 @@ impl Cpu {
 
             print!(" {:X} ", instr);
 
+            let start_pc = self.pc;
+            if let Some(tracer) = &mut self.tracer {
+                if let Err(e) = tracer.record(self.cycles, start_pc, instr, self.i, self.sp, &self.reg) {
+                    println!("Error writing trace, stopped tracing: {}", e);
+                    self.tracer = None;
+                }
+            }
             self.pc += 2; //increment PC here so that jump functions work
 
-            if instr == 0x00E0 {                        //00E0 - CLS
+            if self.execute_platform_instruction(instr, start_pc, memory, display_module, timer_module)? {
+                //the platform's own version of the instruction ran
+            } else if instr == 0x00E0 {                 //00E0 - CLS
                 println!("CLS");
                 display_module.clear(); //clear display buffer
             } else if instr == 0x00EE {                 //00EE - RET
                 println!("RET");
+                if self.sp == 0 {
+                    return self.raise_fault(Fault::StackUnderflow, start_pc);
+                }
+                //a stack kept in memory may have been changed by the program
+                if let Some(address) = memory.get_map().get_stack_address(self.sp) {
+                    self.stack[self.sp as usize] = (memory.get_memory(address) as u16) << 8 | memory.get_memory(address + 1) as u16;
+                }
                 self.pc = self.stack[self.sp as usize];
                 self.sp -= 1; //sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
             } else if instr & 0xF000 == 0x1000 {        //1nnn - JP addr
@@ -58,8 +164,15 @@ impl Cpu {
             } else if instr & 0xF000 == 0x2000 {        //2nnn - CALL addr
                 let call_addr = self.get_nnn(instr);
                 println!("CALL {:X}", call_addr);
+                if self.sp as usize + 1 >= self.stack.len() {
+                    return self.raise_fault(Fault::StackOverflow, start_pc);
+                }
                 self.sp += 1;
                 self.stack[self.sp as usize] = self.pc;
+                if let Some(address) = memory.get_map().get_stack_address(self.sp) {
+                    memory.set_memory(address, (self.pc >> 8) as u8);
+                    memory.set_memory(address + 1, self.pc as u8);
+                }
                 self.pc = call_addr; //increment stack pointer, add current PC to stack, then set PC to addr
             } else if instr & 0xF000 == 0x3000 {        //3xkk - SE Vx, byte
                 let se_x = self.get_nibble(instr, 1) as usize;
@@ -101,17 +214,17 @@ impl Cpu {
                 let or_x = self.get_nibble(instr, 1) as usize;
                 let or_y = self.get_nibble(instr, 2) as usize;
                 println!("OR V{:X}, V{:X}", or_x, or_y);
-                self.reg[or_x] = self.reg[or_x] | self.reg[or_y]; //bitwise-or Vx and Vy, store in Vx
+                self.reg[or_x] |= self.reg[or_y]; //bitwise-or Vx and Vy, store in Vx
             } else if instr & 0xF00F == 0x8002 {        //8xy2 - AND Vx, Vy
                 let and_x = self.get_nibble(instr, 1) as usize;
                 let and_y = self.get_nibble(instr, 2) as usize;
                 println!("AND V{:X}, V{:X}", and_x, and_y);
-                self.reg[and_x] = self.reg[and_x] & self.reg[and_y]; //bitwise-and Vx and Vy, store in Vx
+                self.reg[and_x] &= self.reg[and_y]; //bitwise-and Vx and Vy, store in Vx
             } else if instr & 0xF00F == 0x8003 {        //8xy3 - XOR Vx, Vy
                 let xor_x = self.get_nibble(instr, 1) as usize;
                 let xor_y = self.get_nibble(instr, 2) as usize;
                 println!("XOR V{:X}, V{:X}", xor_x, xor_y);
-                self.reg[xor_x] = self.reg[xor_x] ^ self.reg[xor_y]; //bitwise-xor Vx and Vy, store in Vx
+                self.reg[xor_x] ^= self.reg[xor_y]; //bitwise-xor Vx and Vy, store in Vx
             } else if instr & 0xF00F == 0x8004 {        //8xy4 - ADD Vx, Vy
                 let add_x = self.get_nibble(instr, 1) as usize;
                 let add_y = self.get_nibble(instr, 2) as usize;
@@ -135,21 +248,20 @@ impl Cpu {
                 } else {
                     self.reg[0xF] = 0;
                 }
-                self.reg[shr_x] = self.reg[shr_x] >> 1;
+                self.reg[shr_x] >>= 1;
             } else if instr & 0xF00F == 0x8007 {        //8xy7 - SUBN Vx, Vy
                 let subn_x = self.get_nibble(instr, 1) as usize;
                 let subn_y = self.get_nibble(instr, 1) as usize;
                 println!("SUBN V{:X}, V{:X}", subn_x, subn_y);
                 self.reg[0xF] = if self.reg[subn_y] > self.reg[subn_x] { 1 } else { 0 };
                 let subn_val = (self.reg[subn_y] as u16) - (self.reg[subn_x] as u16);
-                let subn_val = subn_val & 0x0000FFFF;
                 let subn_val = subn_val as u8;
                 self.reg[subn_x] = subn_val;
             } else if instr & 0xF00F == 0x800E {        //8xyE - SHL Vx {, Vy}
                 let shl_x = self.get_nibble(instr, 1) as usize;
                 println!("SHL V{:X}", shl_x);
                 self.reg[0xF] = if (self.reg[shl_x] & 0x80) >> 7 == 1 { 1 } else { 0 };
-                self.reg[shl_x] = self.reg[shl_x] << 1;
+                self.reg[shl_x] <<= 1;
             } else if instr & 0xF00F == 0x9000 { //9xy0 - SNE Vx, Vy
                 let sne_x = self.get_nibble(instr, 1) as usize;
                 let sne_y = self.get_nibble(instr, 2) as usize;
@@ -169,7 +281,7 @@ impl Cpu {
                 let rnd_x = self.get_nibble(instr, 1) as usize;
                 let rnd_kk = self.get_kk(instr);
                 println!("RND V{:X}, {:X}", rnd_x, rnd_kk);
-                let rnd_num: u8 = rand::thread_rng().gen();
+                let rnd_num: u8 = self.rng.next_byte();
                 self.reg[rnd_x] = rnd_num & rnd_kk; //generate random number 0-255, bitwise-and with kk
             } else if instr & 0xF000 == 0xD000 {        //Dxyn - DRW Vx, Vy, nibble
                 let drw_x = self.get_nibble(instr, 1) as usize;
@@ -185,13 +297,13 @@ impl Cpu {
                 self.reg[0xF] = if collision { 1 } else { 0 };
             } else if instr & 0xF0FF == 0xE09E { //Ex9E - SKP Vx
                 let skp_x = self.get_nibble(instr, 1) as usize;
-                if keyboard_module.get_key(self.reg[skp_x]) == true {
+                if keyboard_module.get_key(self.reg[skp_x]) {
                     self.pc += 2; //if keyboard key associated with Vx is pressed, skip next instruction
                 }
             } else if instr & 0xF0FF == 0xE0A1 {          //ExA1 - SKNP Vx
                 let sknp_x = self.get_nibble(instr, 1) as usize;
                 println!("SKNP V{:X}", sknp_x);
-                if keyboard_module.get_key(self.reg[sknp_x]) == false { //if key is not pressed, add 2 to pc
+                if !keyboard_module.get_key(self.reg[sknp_x]) { //if key is not pressed, add 2 to pc
                     self.pc += 2;
                 }
             } else if instr & 0xF0FF == 0xF007 {            //Fx07 - LD Vx, DT
@@ -201,8 +313,7 @@ impl Cpu {
             } else if instr & 0xF0FF == 0xF00A {        //Fx0A - LD Vx, K
                 let ld_x = self.get_nibble(instr, 1);
                 println!("LD V{:X}, K", ld_x);
-                self.wait = true; //stop cpu execution until a key is pressed, load key into Vx
-                self.key_reg = ld_x;
+                self.key_wait = KeyWait::Press { reg: ld_x as usize }; //stop cpu execution until a key is pressed, load key into Vx
             } else if instr & 0xF0FF == 0xF015 {        //Fx15 - LD DT, Vx
                 let ld_dt_x = self.get_nibble(instr, 1) as usize;
                 println!("LD DT, V{:X}", ld_dt_x);
@@ -218,7 +329,7 @@ impl Cpu {
             } else if instr & 0xF0FF == 0xF029 {        //Fx29 - LD F, Vx
                 let ld_x = self.get_nibble(instr, 1) as usize;
                 println!("LD F, V{:X}", ld_x);
-                self.i = ld_x as u16 * 5;
+                self.i = memory.get_font_address(self.reg[ld_x]);
             } else if instr & 0xF0FF == 0xF033 {          //Fx33 - LD B, Vx
                 let ld_x = self.get_nibble(instr, 1) as usize;
                 println!("LD B, V{:X}", ld_x);
@@ -238,11 +349,160 @@ impl Cpu {
                 for n in 0..(ld_x + 1) {
                     self.reg[n] = memory.get_memory(self.i + n as u16);
                 }
+            } else if instr & 0xF000 == 0x0000 {        //0nnn - SYS addr
+                let sys_addr = self.get_nnn(instr);
+                println!("SYS {:X}", sys_addr);
+                match (self.sys_handlers.get(sys_addr), self.sys_mode) {
+                    (Some(handler), _) => handler(self, memory, display_module),
+                    (None, SysMode::Ignore) => {}
+                    (None, SysMode::Halt) => self.pc = start_pc,
+                    (None, SysMode::Error) => return self.raise_fault(Fault::MachineCode(sys_addr), start_pc)
+                }
             } else {
                 println!("INSTRUCTION NOT SUPPORTED.");
             }
+
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record(start_pc, instr, self.pc);
+            }
+            if let Some(profiler) = &mut self.profiler {
+                profiler.record(start_pc, instr, self.sp);
+            }
         }
 
+        Ok(())
+    }
+
+    /// Instructions that only exist on the current platform, or do something else there.
+    /// Returns whether `instr` was one of them.
+    fn execute_platform_instruction(&mut self, instr: u16, start_pc: u16, memory: &mut Memory, display_module: &mut DisplayModule, timer_module: &mut TimerModule) -> Result<bool, Fault> {
+
+        let x = self.get_nibble(instr, 1) as usize;
+        let y = self.get_nibble(instr, 2) as usize;
+        let n = self.get_nibble(instr, 3);
+        let kk = self.get_kk(instr);
+
+        match self.platform {
+            Platform::Chip8X => {
+                if instr & 0xF00F == 0x5001 {                   //5xy1 - ADD Vx, Vy, each nibble separately and mod 8
+                    println!("ADD.N V{:X}, V{:X}", x, y);
+                    let (vx, vy) = (self.reg[x], self.reg[y]);
+                    self.reg[x] = ((((vx >> 4) + (vy >> 4)) % 8) << 4) | (((vx & 0xF) + (vy & 0xF)) % 8);
+                } else if instr & 0xF00F == 0xB000 {            //Bxy0 - COL, 8x4 zones from Vx (x and width) and Vx+1 (y and height), color Vy
+                    println!("COL V{:X}, V{:X}", x, y);
+                    let (horizontal, vertical) = (self.reg[x], self.reg[(x + 1) & 0xF]);
+                    display_module.color_zones((horizontal & 0xF) as usize, (horizontal >> 4) as usize, (vertical & 0xF) as usize, (vertical >> 4) as usize, self.reg[y]);
+                } else if instr & 0xF000 == 0xB000 {            //Bxyn - COL, n rows at (Vx, Vy), color Vx+1
+                    println!("COL V{:X}, V{:X}, {:X}", x, y, n);
+                    display_module.color_rows(self.reg[x] as usize, self.reg[y] as usize, n as usize, self.reg[(x + 1) & 0xF]);
+                } else if instr & 0xF0FF == 0xE0F2 {            //ExF2 - SKP Vx on the second keypad, which is never attached
+                    println!("SKP2 V{:X}", x);
+                } else if instr & 0xF0FF == 0xE0F5 {            //ExF5 - SKNP Vx on the second keypad
+                    println!("SKNP2 V{:X}", x);
+                    self.pc += 2;
+                } else if instr & 0xF0FF == 0xF0F8 {            //FxF8 - OUT Vx, sets the tone, sound is a plain beep here
+                    println!("OUT V{:X}", x);
+                } else if instr & 0xF0FF == 0xF0FB {            //FxFB - IN Vx, nothing is attached to the input port
+                    println!("IN V{:X}", x);
+                    self.reg[x] = 0;
+                } else {
+                    return Ok(false);
+                }
+            }
+            Platform::Chip8E => {
+                if instr == 0x00ED {                            //00ED - STOP
+                    println!("STOP");
+                    self.pc = start_pc;
+                } else if instr == 0x00F2 {                     //00F2 - NOP
+                    println!("NOP");
+                } else if instr == 0x0151 {                     //0151 - wait for the delay timer to run out
+                    println!("WAIT DT");
+                    if timer_module.get_delay_register() != 0 {
+                        self.pc = start_pc;
+                    }
+                } else if instr == 0x0188 {                     //0188 - skip the next instruction
+                    println!("SKIP");
+                    self.pc += 2;
+                } else if instr & 0xF00F == 0x5001 {            //5xy1 - SGT Vx, Vy
+                    println!("SGT V{:X}, V{:X}", x, y);
+                    if self.reg[x] > self.reg[y] {
+                        self.pc += 2;
+                    }
+                } else if instr & 0xF00F == 0x5002 {            //5xy2 - LD [I], Vx-Vy, I moves past them
+                    println!("LD [I], V{:X}-V{:X}", x, y);
+                    if self.i as usize + y.saturating_sub(x) >= memory.get_size() {
+                        self.raise_fault(Fault::IOutOfRange, start_pc)?;
+                    }
+                    for reg in x..=y {
+                        memory.set_memory(self.i, self.reg[reg]);
+                        self.i += 1;
+                    }
+                } else if instr & 0xF00F == 0x5003 {            //5xy3 - LD Vx-Vy, [I], I moves past them
+                    println!("LD V{:X}-V{:X}, [I]", x, y);
+                    if self.i as usize + y.saturating_sub(x) >= memory.get_size() {
+                        self.raise_fault(Fault::IOutOfRange, start_pc)?;
+                    }
+                    for reg in x..=y {
+                        self.reg[reg] = memory.get_memory(self.i);
+                        self.i += 1;
+                    }
+                } else if instr & 0xF00F == 0x9001 {            //9xy1 - MUL Vx, Vy, high byte in VF
+                    println!("MUL V{:X}, V{:X}", x, y);
+                    let product = self.reg[x] as u16 * self.reg[y] as u16;
+                    self.reg[x] = product as u8;
+                    self.reg[0xF] = (product >> 8) as u8;
+                } else if instr & 0xF00F == 0x9002 {            //9xy2 - DIV Vx, Vy, remainder in VF
+                    println!("DIV V{:X}, V{:X}", x, y);
+                    let (vx, vy) = (self.reg[x], self.reg[y]);
+                    self.reg[x] = vx.checked_div(vy).unwrap_or(0); //dividing by 0 gives 0 with Vx left over
+                    self.reg[0xF] = vx.checked_rem(vy).unwrap_or(vx);
+                } else if instr & 0xF00F == 0x9003 {            //9xy3 - BCD of the 16 bit Vx:Vy, 5 digits at I
+                    println!("LD B, V{:X}:V{:X}", x, y);
+                    if self.i as usize + 4 >= memory.get_size() {
+                        self.raise_fault(Fault::IOutOfRange, start_pc)?;
+                    }
+                    let value = ((self.reg[x] as u16) << 8) | self.reg[y] as u16;
+                    for (offset, divisor) in [10000, 1000, 100, 10, 1].iter().enumerate() {
+                        memory.set_memory(self.i + offset as u16, (value / divisor % 10) as u8);
+                    }
+                } else if instr & 0xFF00 == 0xBB00 {            //BBkk - JP back kk bytes
+                    println!("JP -{:X}", kk);
+                    self.pc = start_pc.wrapping_sub(kk as u16);
+                } else if instr & 0xFF00 == 0xBF00 {            //BFkk - JP forward kk bytes
+                    println!("JP +{:X}", kk);
+                    self.pc = start_pc.wrapping_add(kk as u16);
+                } else if instr & 0xF0FF == 0xF003 {            //Fx03 - OUT Vx to port 3, nothing is attached
+                    println!("OUT V{:X}", x);
+                } else if instr & 0xF0FF == 0xF01B {            //Fx1B - skip Vx bytes
+                    println!("SKIP V{:X}", x);
+                    self.pc += self.reg[x] as u16;
+                } else if instr & 0xF0FF == 0xF04F {            //Fx4F - load the delay timer from Vx and wait for it to run out
+                    println!("LD DT, V{:X} WAIT", x);
+                    if !self.delay_wait {
+                        timer_module.set_delay_register(self.reg[x]);
+                        self.delay_wait = true;
+                    }
+                    if timer_module.get_delay_register() != 0 {
+                        self.pc = start_pc;
+                    } else {
+                        self.delay_wait = false;
+                    }
+                } else if instr & 0xF0FF == 0xF0E3 || instr & 0xF0FF == 0xF0E7 { //FxE3/FxE7 - IN Vx from port 3, nothing is attached
+                    println!("IN V{:X}", x);
+                    self.reg[x] = 0;
+                } else {
+                    return Ok(false);
+                }
+            }
+            Platform::Chip8 | Platform::SuperChip | Platform::XoChip | Platform::Chip10 => return Ok(false)
+        }
+        Ok(true)
+    }
+
+    fn raise_fault(&mut self, fault: Fault, pc: u16) -> Result<(), Fault> {
+        self.pc = pc;
+        self.fault = Some(fault);
+        Err(fault)
     }
 
     fn get_nnn(&self, instr: u16) -> u16 {
@@ -256,12 +516,188 @@ impl Cpu {
         ((instr >> shift_amt) & 0x000F) as u8
     }
 
-    pub fn get_wait(&self) -> bool { self.wait }
+    pub fn get_pc(&self) -> u16 { self.pc }
+    pub fn get_i(&self) -> u16 { self.i }
+    pub fn get_sp(&self) -> u8 { self.sp }
+    pub fn get_reg(&self, x: usize) -> u8 { self.reg[x] }
+
+    pub fn set_pc(&mut self, val: u16) { self.pc = val; }
+    pub fn set_i(&mut self, val: u16) { self.i = val; }
+    pub fn set_sp(&mut self, val: u8) { self.sp = val & 0xF; } //stack has 16 entries
+    pub fn set_reg(&mut self, x: usize, val: u8) { self.reg[x] = val; }
+
+    pub fn get_rng_seed(&self) -> u64 { self.rng.get_seed() }
+    pub fn get_platform(&self) -> Platform { self.platform }
+    pub fn get_rng_mode(&self) -> RngMode { self.rng.get_mode() }
+    pub fn get_cycles(&self) -> u64 { self.cycles }
+    pub fn get_fault(&self) -> Option<Fault> { self.fault }
+
+    /// Return addresses on the stack, innermost call first.
+    pub fn get_call_stack(&self) -> Vec<u16> {
+        self.stack[1..=self.sp as usize].iter().rev().copied().collect()
+    }
+
+    /// Start recording which instructions run, kept across resets.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    pub fn get_coverage(&self) -> Option<&Coverage> { self.coverage.as_ref() }
+
+    /// Start counting cycles per subroutine, kept across resets.
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn get_profiler(&self) -> Option<&Profiler> { self.profiler.as_ref() }
+
+    /// Pick what SYS calls without a handler do.
+    pub fn set_sys_mode(&mut self, mode: SysMode) {
+        self.sys_mode = mode;
+    }
+
+    /// Run `handler` in place of the machine code routine at `address` when the program calls it.
+    pub fn register_sys_handler(&mut self, address: u16, handler: SysHandler) {
+        self.sys_handlers.register(address, handler);
+    }
+
+    /// Write every executed instruction to a trace file.
+    pub fn start_trace(&mut self, tracer: TraceWriter) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Flush and close the trace file, if there is one.
+    pub fn finish_trace(&mut self) -> std::io::Result<()> {
+        match self.tracer.take() {
+            Some(mut tracer) => tracer.flush(),
+            None => Ok(())
+        }
+    }
+
+    //called once per 60Hz frame alongside the timers
+    pub fn tick_frame(&mut self) {
+        self.rng.tick_frame();
+    }
+
+    /// Zero the registers and stack and restart the random sequence from its seed.
+    ///
+    /// The cycle counter keeps counting so recorded movies stay in sync across resets.
+    pub fn reset(&mut self) {
+        self.pc = self.platform.get_program_start();
+        self.i = 0;
+        self.sp = 0;
+        self.stack = [0; 16];
+        self.reg = [0; 16];
+        self.key_wait = KeyWait::Idle;
+        self.last_keys = [false; 16];
+        self.delay_wait = false;
+        self.fault = None;
+        self.rng.reseed(self.rng.get_seed());
+    }
+
+    /// Turn on a platform's own instructions, CHIP-8X also gets its 02A0 background color routine.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        if platform == Platform::Chip8X {
+            self.register_sys_handler(0x2A0, sys_call::builtin("background").expect("Error: missing built-in sys handler"));
+        }
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_u16(self.pc);
+        writer.put_u16(self.i);
+        writer.put_u8(self.sp);
+        for addr in self.stack.iter() {
+            writer.put_u16(*addr);
+        }
+        writer.put_bytes(&self.reg);
+        match self.key_wait {
+            KeyWait::Idle => writer.put_bytes(&[0, 0, 0]),
+            KeyWait::Press { reg } => writer.put_bytes(&[1, reg as u8, 0]),
+            KeyWait::Release { reg, key } => writer.put_bytes(&[2, reg as u8, key])
+        }
+        for key in self.last_keys.iter() {
+            writer.put_bool(*key);
+        }
+        writer.put_bool(self.delay_wait);
+        writer.put_u64(self.cycles);
+        self.rng.save_state(writer);
+    }
+
+    pub fn read_state(reader: &mut StateReader) -> std::io::Result<CpuState> {
+        let pc = reader.get_u16()?;
+        let i = reader.get_u16()?;
+        let sp = reader.get_u8()? & 0xF;
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = reader.get_u16()?;
+        }
+        let mut reg = [0; 16];
+        reg.copy_from_slice(reader.get_bytes(16)?);
+        let key_wait = reader.get_bytes(3)?;
+        let key_wait = match key_wait[0] {
+            1 => KeyWait::Press { reg: key_wait[1] as usize & 0xF },
+            2 => KeyWait::Release { reg: key_wait[1] as usize & 0xF, key: key_wait[2] & 0xF },
+            _ => KeyWait::Idle
+        };
+        let mut last_keys = [false; 16];
+        for key in last_keys.iter_mut() {
+            *key = reader.get_bool()?;
+        }
+        let delay_wait = reader.get_bool()?;
+        let cycles = reader.get_u64()?;
+        let mut rng = Prng::new(RngMode::Xorshift, 0);
+        rng.load_state(reader)?;
+        Ok(CpuState { pc, i, sp, stack, reg, key_wait, last_keys, delay_wait, cycles, rng })
+    }
+
+    pub fn apply_state(&mut self, state: CpuState) {
+        self.pc = state.pc;
+        self.i = state.i;
+        self.sp = state.sp;
+        self.stack = state.stack;
+        self.reg = state.reg;
+        self.key_wait = state.key_wait;
+        self.last_keys = state.last_keys;
+        self.delay_wait = state.delay_wait;
+        self.cycles = state.cycles;
+        self.rng = state.rng;
+        self.fault = None;
+    }
+
+    /// Advance the Fx0A state machine with the current key state.
+    ///
+    /// Returns true while the cpu is blocked, the cycle that completes the wait is spent here as well.
+    fn update_key_wait(&mut self, keys: &[bool; 16]) -> bool {
+        match self.key_wait {
+            KeyWait::Idle => false,
+            KeyWait::Press { reg } => {
+                let new_press = (0..16u8).find(|key| keys[*key as usize] && !self.last_keys[*key as usize]);
+                if let Some(key) = new_press {
+                    match self.quirks.key_wait {
+                        KeyWaitQuirk::Press => self.finish_key_wait(reg, key),
+                        KeyWaitQuirk::Release => self.key_wait = KeyWait::Release { reg, key }
+                    }
+                }
+                true
+            }
+            KeyWait::Release { reg, key } => {
+                if !keys[key as usize] {
+                    self.finish_key_wait(reg, key);
+                }
+                true
+            }
+        }
+    }
 
-    pub fn key_press(&mut self, val: u8) {
-        self.wait = false;
-        self.reg[self.key_reg as usize] = val;
-        println!("---------------------------V{:X}: {:X}", self.key_reg, val);
+    fn finish_key_wait(&mut self, reg: usize, key: u8) {
+        self.key_wait = KeyWait::Idle;
+        self.reg[reg] = key;
+        println!("---------------------------V{:X}: {:X}", reg, key);
     }
 
 }
@@ -1,24 +1,129 @@
 use crate::memory::Memory;
-use std::convert::TryInto;
+use crate::save_state::{StateReader, StateWriter};
 
 const STACK_SIZE: usize = 2;
 
+/// Colors used for lit and unlit pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub name: &'static str,
+    pub foreground: [u8; 3],
+    pub background: [u8; 3]
+}
+
+pub const PALETTES: [Palette; 4] = [
+    Palette { name: "MONO", foreground: [0xFF, 0xFF, 0xFF], background: [0x00, 0x00, 0x00] },
+    Palette { name: "AMBER", foreground: [0xFF, 0xB0, 0x00], background: [0x1A, 0x0E, 0x00] },
+    Palette { name: "GREEN", foreground: [0x33, 0xFF, 0x66], background: [0x00, 0x14, 0x05] },
+    Palette { name: "LCD", foreground: [0x0F, 0x38, 0x0F], background: [0x9B, 0xBC, 0x0F] }
+];
+
+//CHIP-8X's VP-590 color board
+const COLOR_ZONE_WIDTH: usize = 8;      //pixels across that share a foreground color
+const COLOR_ZONE_HEIGHT: usize = 4;     //rows colored at once by Bxy0, BxyN colors single rows
+const DEFAULT_ZONE_COLOR: u8 = 1;       //red
+const FOREGROUND_COLORS: [[u8; 3]; 8] = [
+    [0x00, 0x00, 0x00], [0xFF, 0x00, 0x00], [0x00, 0x00, 0xFF], [0xFF, 0x00, 0xFF],
+    [0x00, 0xFF, 0x00], [0xFF, 0xFF, 0x00], [0x00, 0xFF, 0xFF], [0xFF, 0xFF, 0xFF]
+];
+const BACKGROUND_COLORS: [[u8; 3]; 4] = [[0x00, 0x00, 0x80], [0x00, 0x00, 0x00], [0x00, 0x80, 0x00], [0x80, 0x00, 0x00]]; //blue, black, green, red
+
+#[derive(Clone)]
 pub struct DisplayModule {
-    frame_buffer: Vec<Vec<bool>>, //64x32 array of bools for black and white frames
+    frame_buffer: Vec<Vec<bool>>, //width x height array of bools for black and white frames
     frame_stack: Vec<Vec<Vec<bool>>>,
     width: u32,
-    height: u32
+    height: u32,
+    palette: Palette,
+    zone_colors: Option<Vec<Vec<u8>>>, //CHIP-8X foreground color of each 8 pixel run of each row, None without the color board
+    background_color: usize,           //index into BACKGROUND_COLORS
+    changed: bool                      //pixels were drawn or cleared since the last take_changed
 }
 
 impl DisplayModule {
     ///Initialize and Return a new DisplayUnit
     pub fn new(width: u32, height: u32) -> Self {
         Self {
-            frame_buffer: vec![ vec![false; 32]; 64],
-            frame_stack: vec![vec![ vec![false; 32]; 64]; STACK_SIZE],
+            frame_buffer: vec![ vec![false; height as usize]; width as usize],
+            frame_stack: vec![vec![ vec![false; height as usize]; width as usize]; STACK_SIZE],
             width,
-            height
+            height,
+            palette: PALETTES[0],
+            zone_colors: None,
+            background_color: 0,
+            changed: false
+        }
+    }
+
+    /// Add CHIP-8X's color board, pixels are drawn in their zone's color instead of the palette's.
+    pub fn enable_color(&mut self) {
+        self.zone_colors = Some(vec![vec![DEFAULT_ZONE_COLOR; self.height as usize]; self.width as usize / COLOR_ZONE_WIDTH]);
+    }
+
+    /// Bxy0: color zones `x..=x + width` across and `y..=y + height` down, in units of 8x4 pixels.
+    pub fn color_zones(&mut self, x: usize, width: usize, y: usize, height: usize, color: u8) {
+        for zone_x in x..=x + width {
+            self.color_rows(zone_x * COLOR_ZONE_WIDTH, y * COLOR_ZONE_HEIGHT, (height + 1) * COLOR_ZONE_HEIGHT, color);
+        }
+    }
+
+    /// BxyN: color `rows` pixel rows of the 8 pixel wide zone holding pixel `x`, starting at row `y`. Zones off the display are ignored.
+    pub fn color_rows(&mut self, x: usize, y: usize, rows: usize, color: u8) {
+        if let Some(zone_colors) = &mut self.zone_colors {
+            if let Some(column) = zone_colors.get_mut(x / COLOR_ZONE_WIDTH) {
+                for row in column.iter_mut().skip(y).take(rows) {
+                    *row = color & 0x7;
+                }
+            }
+        }
+    }
+
+    /// 02A0: step the CHIP-8X background through blue, black, green and red.
+    pub fn cycle_background(&mut self) {
+        self.background_color = (self.background_color + 1) % BACKGROUND_COLORS.len();
+    }
+
+    pub fn get_width(&self) -> u32 { self.width }
+    pub fn get_height(&self) -> u32 { self.height }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.frame_buffer[x][y]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        self.frame_buffer[x][y] = on;
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for column in self.frame_buffer.iter() {
+            for pixel in column.iter() {
+                writer.put_bool(*pixel);
+            }
+        }
+        if let Some(zone_colors) = &self.zone_colors {
+            writer.put_bytes(&zone_colors.concat());
+            writer.put_bytes(&[self.background_color as u8]);
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        for column in self.frame_buffer.iter_mut() {
+            for pixel in column.iter_mut() {
+                *pixel = reader.get_bool()?;
+            }
         }
+        if let Some(zone_colors) = &mut self.zone_colors {
+            for column in zone_colors.iter_mut() {
+                let len = column.len();
+                column.copy_from_slice(reader.get_bytes(len)?);
+            }
+            self.background_color = reader.get_bytes(1)?[0] as usize % BACKGROUND_COLORS.len();
+        }
+        Ok(())
     }
 
     pub fn draw_sprite(&mut self, in_x: u8, in_y: u8, i: u16, memory: &Memory) -> bool {
@@ -29,12 +134,13 @@ impl DisplayModule {
 
         for n in 0..8 {
 
-            let x = ((in_x as usize + n) % self.width as usize) as usize;
+            let x = (in_x as usize + n) % self.width as usize;
             let y = (in_y % self.height as u8) as usize;
 
             let sprite_pixel = ((sprite >> (7 - n)) & 1) != 0;
 
             self.frame_buffer[x][y] ^= sprite_pixel;
+            self.changed |= sprite_pixel;
 
             if sprite_pixel && !self.frame_buffer[x][y] {
                 collision = true;
@@ -47,22 +153,30 @@ impl DisplayModule {
 
     //clears buffer (set all to 0)
     pub fn clear(&mut self) {
-        self.frame_buffer = vec![ vec![false; 32]; 64];
+        self.frame_buffer = vec![ vec![false; self.height as usize]; self.width as usize];
+        self.changed = true;
+    }
+
+    /// Whether DRW or CLS changed the pixels since the last call.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
     }
 
     /// Draw the frame_buffer array contents to the actual frame buffer.
     ///
     /// Assumes the default texture format: `wgpu::TextureFormat::Rgba8UnormSrgb`
-    ///
-
-    pub fn draw(&mut self, frame: &mut [u8]) {
+    /// The frame may be wider or taller than the display when something is drawn beside it, those pixels are left alone.
+    pub fn draw(&mut self, frame: &mut [u8], frame_width: usize) {
 
         //draw current frame
         for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
 
             //get coordinates
-            let x = i % self.width as usize;
-            let y = i / self.width as usize;
+            let x = i % frame_width;
+            let y = i / frame_width;
+            if x >= self.width as usize || y >= self.height as usize {
+                continue;
+            }
 
             //set pixel to white if pixel should be displayed, transparent white if it was displayed last frame, otherwise set to black
             let rgba = self.generate_glow(x,y);
@@ -87,11 +201,19 @@ impl DisplayModule {
             if self.frame_stack[i][x][y] {
                 let pixel_opacity = (0xFF / (stack_len - i)) / 2;
                 let pixel_opacity:u8 = pixel_opacity as u8;
-                return [0xFF,0xFF,0xFF, pixel_opacity];
+                let [r, g, b] = match &self.zone_colors {
+                    Some(zone_colors) => FOREGROUND_COLORS[zone_colors[x / COLOR_ZONE_WIDTH][y] as usize],
+                    None => self.palette.foreground
+                };
+                return [r, g, b, pixel_opacity];
             }
         }
 
-        [0x00,0x00,0x00,0x00]
+        let [r, g, b] = match &self.zone_colors {
+            Some(_) => BACKGROUND_COLORS[self.background_color],
+            None => self.palette.background
+        };
+        [r, g, b, 0x00]
     }
 
 
@@ -0,0 +1,149 @@
+use std::fs;
+use gilrs::{Axis, Button, Gilrs};
+
+use crate::input_source::InputSource;
+
+//how far a stick has to be pushed before it counts as a key press
+const AXIS_THRESHOLD: f32 = 0.5;
+
+/// Which gamepad buttons and stick directions hold down which chip-8 keys.
+///
+/// Mapping files have one binding per line, `#` starts a comment:
+///
+/// ```text
+/// button South 5
+/// axis LeftStickX - 4
+/// axis LeftStickX + 6
+/// ```
+pub struct GamepadMapping {
+    buttons: Vec<(Button, u8)>,
+    axes: Vec<(Axis, bool, u8)> //axis, positive direction, key
+}
+
+impl GamepadMapping {
+
+    /// D-pad and left stick move around the 2/4/6/8 cross that most games use, face buttons cover the rest.
+    pub fn default_mapping() -> Self {
+        Self {
+            buttons: vec![
+                (Button::DPadUp, 0x2), (Button::DPadDown, 0x8), (Button::DPadLeft, 0x4), (Button::DPadRight, 0x6),
+                (Button::South, 0x5), (Button::East, 0x0), (Button::West, 0xA), (Button::North, 0xB),
+                (Button::Select, 0xC), (Button::Start, 0x1)
+            ],
+            axes: vec![
+                (Axis::LeftStickX, false, 0x4), (Axis::LeftStickX, true, 0x6),
+                (Axis::LeftStickY, true, 0x2), (Axis::LeftStickY, false, 0x8) //gilrs reports up as positive
+            ]
+        }
+    }
+
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+
+        let mut mapping = Self { buttons: Vec::new(), axes: Vec::new() };
+
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => {}
+                ["button", button, key] => mapping.buttons.push((parse_button(button)?, parse_key(key)?)),
+                ["axis", axis, direction, key] => {
+                    let positive = match *direction {
+                        "+" => true,
+                        "-" => false,
+                        other => return Err(format!("unknown axis direction {}", other))
+                    };
+                    mapping.axes.push((parse_axis(axis)?, positive, parse_key(key)?));
+                }
+                _ => return Err(format!("invalid gamepad mapping line: {}", line))
+            }
+        }
+
+        Ok(mapping)
+    }
+
+}
+
+/// Every connected gamepad, read through gilrs.
+pub struct GamepadSource {
+    gilrs: Gilrs,
+    mapping: GamepadMapping
+}
+
+impl GamepadSource {
+
+    pub fn new(mapping: GamepadMapping) -> Result<Self, String> {
+        let gilrs = Gilrs::new().map_err(|e| e.to_string())?;
+        Ok(Self { gilrs, mapping })
+    }
+
+}
+
+impl InputSource for GamepadSource {
+    fn poll(&mut self, keys: &mut [bool; 16]) {
+
+        //gilrs only updates its cached gamepad state while events are drained
+        while self.gilrs.next_event().is_some() {}
+
+        for (_, gamepad) in self.gilrs.gamepads() {
+            for (button, key) in self.mapping.buttons.iter() {
+                if gamepad.is_pressed(*button) {
+                    keys[*key as usize] = true;
+                }
+            }
+            for (axis, positive, key) in self.mapping.axes.iter() {
+                let value = gamepad.value(*axis);
+                if (*positive && value > AXIS_THRESHOLD) || (!*positive && value < -AXIS_THRESHOLD) {
+                    keys[*key as usize] = true;
+                }
+            }
+        }
+
+    }
+}
+
+fn parse_key(key: &str) -> Result<u8, String> {
+    u8::from_str_radix(key, 16).ok().filter(|key| *key < 16).ok_or_else(|| format!("invalid chip-8 key {}", key))
+}
+
+fn parse_button(name: &str) -> Result<Button, String> {
+    let button = match name {
+        "South" => Button::South,
+        "East" => Button::East,
+        "North" => Button::North,
+        "West" => Button::West,
+        "C" => Button::C,
+        "Z" => Button::Z,
+        "LeftTrigger" => Button::LeftTrigger,
+        "LeftTrigger2" => Button::LeftTrigger2,
+        "RightTrigger" => Button::RightTrigger,
+        "RightTrigger2" => Button::RightTrigger2,
+        "Select" => Button::Select,
+        "Start" => Button::Start,
+        "Mode" => Button::Mode,
+        "LeftThumb" => Button::LeftThumb,
+        "RightThumb" => Button::RightThumb,
+        "DPadUp" => Button::DPadUp,
+        "DPadDown" => Button::DPadDown,
+        "DPadLeft" => Button::DPadLeft,
+        "DPadRight" => Button::DPadRight,
+        other => return Err(format!("unknown gamepad button {}", other))
+    };
+    Ok(button)
+}
+
+fn parse_axis(name: &str) -> Result<Axis, String> {
+    let axis = match name {
+        "LeftStickX" => Axis::LeftStickX,
+        "LeftStickY" => Axis::LeftStickY,
+        "LeftZ" => Axis::LeftZ,
+        "RightStickX" => Axis::RightStickX,
+        "RightStickY" => Axis::RightStickY,
+        "RightZ" => Axis::RightZ,
+        "DPadX" => Axis::DPadX,
+        "DPadY" => Axis::DPadY,
+        other => return Err(format!("unknown gamepad axis {}", other))
+    };
+    Ok(axis)
+}
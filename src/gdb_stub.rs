@@ -0,0 +1,456 @@
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::chip8::Chip8;
+
+//registers as gdb sees them: V0-VF, I, PC, SP
+const NUM_REGISTERS: usize = 19;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.c8emu.chip8">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+/// Minimal GDB remote serial protocol server.
+///
+/// Supports reading and writing registers and memory, software and hardware breakpoints
+/// (both are checked against PC before each instruction), continue, single-step and Ctrl-C.
+/// `monitor backtrace` prints the chip8 call stack, which gdb can't unwind by itself.
+/// The emulator keeps running until a debugger connects, then stops until told to continue.
+pub struct GdbStub {
+    listener: TcpListener,
+    conn: Option<TcpStream>,
+    buf: Vec<u8>,
+    breakpoints: HashSet<u16>,
+    halted: bool,
+    step_over: bool, //resuming from a breakpoint, don't stop on it again straight away
+    no_ack: bool
+}
+
+impl GdbStub {
+
+    pub fn bind(port: u16) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        println!("GDB stub listening on 127.0.0.1:{}", port);
+
+        Ok(Self {
+            listener,
+            conn: None,
+            buf: Vec::new(),
+            breakpoints: HashSet::new(),
+            halted: false,
+            step_over: false,
+            no_ack: false
+        })
+    }
+
+    /// True while a debugger has the cpu stopped, the main loop shouldn't run any cycles.
+    pub fn is_halted(&self) -> bool { self.halted }
+
+    /// Accept a debugger and handle any packets it sent. Call once per event loop iteration.
+    pub fn poll(&mut self, chip8: &mut Chip8) {
+
+        if self.conn.is_none() {
+            if let Ok((stream, addr)) = self.listener.accept() {
+                println!("GDB connected from {}", addr);
+                stream.set_nonblocking(true).ok();
+                stream.set_nodelay(true).ok();
+                self.conn = Some(stream);
+                self.buf.clear();
+                self.no_ack = false;
+                self.halted = true;
+            }
+            return;
+        }
+
+        let mut read_buf = [0; 1024];
+        loop {
+            let result = match &mut self.conn {
+                Some(conn) => conn.read(&mut read_buf),
+                None => return
+            };
+            match result {
+                Ok(0) => {
+                    self.disconnect();
+                    return;
+                }
+                Ok(len) => self.buf.extend_from_slice(&read_buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.disconnect();
+                    return;
+                }
+            }
+        }
+
+        while let Some(packet) = self.next_packet() {
+            self.handle_packet(&packet, chip8);
+        }
+    }
+
+    /// Call before each instruction while running. Returns true, and reports the stop to the
+    /// debugger, if the cpu is sitting on a breakpoint.
+    pub fn check_breakpoint(&mut self, chip8: &Chip8) -> bool {
+        if self.step_over {
+            self.step_over = false;
+            return false;
+        }
+        if self.conn.is_some() && self.breakpoints.contains(&chip8.cpu.get_pc()) {
+            self.halted = true;
+            self.send_packet("S05");
+            return true;
+        }
+        false
+    }
+
+    fn disconnect(&mut self) {
+        println!("GDB disconnected");
+        self.conn = None;
+        self.breakpoints.clear();
+        self.halted = false;
+    }
+
+    //pull the next complete packet out of the receive buffer, acking it and handling interrupts on the way
+    fn next_packet(&mut self) -> Option<String> {
+        loop {
+            match self.buf.first()? {
+                b'$' => {
+                    let end = self.buf.iter().position(|b| *b == b'#')?;
+                    if self.buf.len() < end + 3 {
+                        return None; //checksum hasn't arrived yet
+                    }
+                    let packet: Vec<u8> = self.buf.drain(..end + 3).collect();
+                    if !self.no_ack {
+                        self.write_raw(b"+");
+                    }
+                    return Some(String::from_utf8_lossy(&packet[1..end]).to_string());
+                }
+                0x03 => {
+                    //ctrl-c from the debugger
+                    self.buf.remove(0);
+                    if !self.halted {
+                        self.halted = true;
+                        self.send_packet("S02");
+                    }
+                }
+                _ => {
+                    self.buf.remove(0); //acks and line noise
+                }
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &str, chip8: &mut Chip8) {
+
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => "S05".to_string(),
+            Some(b'g') => (0..NUM_REGISTERS).map(|reg| read_register(chip8, reg)).collect(),
+            Some(b'G') => match decode_hex(&packet[1..]) {
+                Some(bytes) => {
+                    let mut pos = 0;
+                    for reg in 0..NUM_REGISTERS {
+                        let size = register_size(reg);
+                        if let Some(val) = bytes.get(pos..pos + size) {
+                            write_register(chip8, reg, val);
+                        }
+                        pos += size;
+                    }
+                    "OK".to_string()
+                }
+                None => "E01".to_string()
+            },
+            Some(b'p') => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(reg) if reg < NUM_REGISTERS => read_register(chip8, reg),
+                _ => "E01".to_string()
+            },
+            Some(b'P') => {
+                let mut parts = packet[1..].splitn(2, '=');
+                match (parts.next().and_then(|reg| usize::from_str_radix(reg, 16).ok()), parts.next().and_then(decode_hex)) {
+                    (Some(reg), Some(val)) if reg < NUM_REGISTERS && val.len() == register_size(reg) => {
+                        write_register(chip8, reg, &val);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string()
+                }
+            }
+            Some(b'm') => match parse_range(&packet[1..], chip8) {
+                Some((addr, len)) => (addr..addr + len).map(|a| format!("{:02x}", chip8.memory.get_memory(a as u16))).collect(),
+                None => "E01".to_string()
+            },
+            Some(b'M') => {
+                let mut parts = packet[1..].splitn(2, ':');
+                match (parts.next().and_then(|range| parse_range(range, chip8)), parts.next().and_then(decode_hex)) {
+                    (Some((addr, len)), Some(data)) => {
+                        for (n, byte) in data.iter().take(len).enumerate() {
+                            chip8.memory.set_memory((addr + n) as u16, *byte);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string()
+                }
+            }
+            Some(b'c') => {
+                self.halted = false;
+                self.step_over = true;
+                return; //reply comes when we stop again
+            }
+            Some(b's') => {
+                chip8.step();
+                "S05".to_string()
+            }
+            Some(b'Z') | Some(b'z') => {
+                //Z0 (software) and Z1 (hardware) breakpoints are handled the same way
+                let fields: Vec<&str> = packet[1..].split(',').collect();
+                match (fields.first(), fields.get(1).and_then(|addr| u16::from_str_radix(addr, 16).ok())) {
+                    (Some(&"0"), Some(addr)) | (Some(&"1"), Some(addr)) => {
+                        if packet.starts_with('Z') {
+                            self.breakpoints.insert(addr);
+                        } else {
+                            self.breakpoints.remove(&addr);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => String::new()
+                }
+            }
+            Some(b'D') => {
+                self.send_packet("OK");
+                self.disconnect();
+                return;
+            }
+            Some(b'k') => {
+                self.disconnect();
+                return;
+            }
+            Some(b'H') => "OK".to_string(),
+            Some(b'Q') if packet == "QStartNoAckMode" => {
+                self.send_packet("OK"); //acked in the old mode, everything after isn't
+                self.no_ack = true;
+                return;
+            }
+            Some(b'q') | Some(b'Q') => self.handle_query(packet, chip8),
+            _ => String::new() //empty reply means unsupported
+        };
+
+        self.send_packet(&reply);
+    }
+
+    fn handle_query(&mut self, packet: &str, chip8: &Chip8) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string()
+        } else if let Some(command) = packet.strip_prefix("qRcmd,") {
+            //gdb's monitor command
+            let command = match decode_hex(command) {
+                Some(command) => String::from_utf8_lossy(&command).to_string(),
+                None => return "E01".to_string()
+            };
+            let output = match command.trim() {
+                "bt" | "backtrace" => chip8.get_backtrace(),
+                _ => "monitor commands: backtrace\n".to_string()
+            };
+            self.send_packet(&format!("O{}", encode_hex(output.as_bytes())));
+            "OK".to_string()
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let mut parts = range.splitn(2, ',');
+            let offset = parts.next().and_then(|v| usize::from_str_radix(v, 16).ok()).unwrap_or(0);
+            let len = parts.next().and_then(|v| usize::from_str_radix(v, 16).ok()).unwrap_or(0);
+            let xml = TARGET_XML.as_bytes();
+            let start = offset.min(xml.len());
+            let end = offset.saturating_add(len).min(xml.len());
+            let prefix = if end == xml.len() { "l" } else { "m" };
+            format!("{}{}", prefix, String::from_utf8_lossy(&xml[start..end]))
+        } else {
+            String::new()
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let packet = format!("${}#{:02x}", data, checksum);
+        self.write_raw(packet.as_bytes());
+    }
+
+    fn write_raw(&mut self, data: &[u8]) {
+        if let Some(conn) = &mut self.conn {
+            //the socket is non-blocking, but packets are tiny so a short write is not worth handling
+            if conn.write_all(data).is_err() {
+                self.disconnect();
+            }
+        }
+    }
+
+}
+
+fn register_size(reg: usize) -> usize {
+    match reg {
+        16 | 17 => 2,
+        _ => 1
+    }
+}
+
+//registers are sent as little-endian hex
+fn read_register(chip8: &Chip8, reg: usize) -> String {
+    match reg {
+        0..=15 => format!("{:02x}", chip8.cpu.get_reg(reg)),
+        16 => encode_u16(chip8.cpu.get_i()),
+        17 => encode_u16(chip8.cpu.get_pc()),
+        _ => format!("{:02x}", chip8.cpu.get_sp())
+    }
+}
+
+fn write_register(chip8: &mut Chip8, reg: usize, val: &[u8]) {
+    let val_u16 = val.first().copied().unwrap_or(0) as u16 | (val.get(1).copied().unwrap_or(0) as u16) << 8;
+    match reg {
+        0..=15 => chip8.cpu.set_reg(reg, val_u16 as u8),
+        16 => chip8.cpu.set_i(val_u16),
+        17 => chip8.cpu.set_pc(val_u16),
+        _ => chip8.cpu.set_sp(val_u16 as u8)
+    }
+}
+
+fn encode_u16(val: u16) -> String {
+    format!("{:02x}{:02x}", val & 0xFF, val >> 8)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+//None unless every character is a hex digit and they pair up into bytes
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.as_bytes().chunks(2).map(|pair| {
+        let high = (pair[0] as char).to_digit(16)?;
+        let low = (pair[1] as char).to_digit(16)?;
+        Some((high << 4 | low) as u8)
+    }).collect()
+}
+
+//"addr,len" in hex, None if it reaches outside of memory
+fn parse_range(range: &str, chip8: &Chip8) -> Option<(usize, usize)> {
+    let mut parts = range.splitn(2, ',');
+    let addr = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+    if addr.checked_add(len)? > chip8.memory.get_size() {
+        return None;
+    }
+    Some((addr, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    //a scripted debugger on the other end of a real socket
+    struct Client {
+        stub: GdbStub,
+        chip8: Chip8,
+        conn: TcpStream
+    }
+
+    impl Client {
+
+        fn connect() -> Self {
+            let mut chip8 = Chip8::for_test(&[0x12, 0x00]);
+
+            let mut stub = GdbStub::bind(0).expect("Error binding test stub");
+            let conn = TcpStream::connect(stub.listener.local_addr().expect("Error reading test stub address")).expect("Error connecting to test stub");
+            conn.set_read_timeout(Some(Duration::from_millis(10))).expect("Error setting timeout");
+            while stub.conn.is_none() {
+                stub.poll(&mut chip8);
+            }
+            Self { stub, chip8, conn }
+        }
+
+        //send a packet, which may hold any bytes, and wait for the reply's payload
+        fn request(&mut self, packet: &[u8]) -> String {
+            let checksum = packet.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+            let mut raw = vec![b'$'];
+            raw.extend_from_slice(packet);
+            raw.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+            self.conn.write_all(&raw).expect("Error writing to test stub");
+
+            let mut reply = Vec::new();
+            for _ in 0..500 {
+                self.stub.poll(&mut self.chip8);
+                let mut buf = [0; 1024];
+                if let Ok(len) = self.conn.read(&mut buf) {
+                    reply.extend_from_slice(&buf[..len]);
+                }
+                if let (Some(start), Some(end)) = (reply.iter().position(|b| *b == b'$'), reply.iter().position(|b| *b == b'#')) {
+                    if reply.len() >= end + 3 {
+                        return String::from_utf8_lossy(&reply[start + 1..end]).to_string();
+                    }
+                }
+            }
+            panic!("no reply to {}", String::from_utf8_lossy(packet));
+        }
+
+    }
+
+    #[test]
+    fn memory_round_trip() {
+        let mut client = Client::connect();
+        assert_eq!(client.request(b"M300,2:abcd"), "OK");
+        assert_eq!(client.request(b"m300,2"), "abcd");
+        assert_eq!(client.request(b"mfff,2"), "E01"); //runs off the end of memory
+    }
+
+    #[test]
+    fn overflowing_range_is_an_error() {
+        let mut client = Client::connect();
+        assert_eq!(client.request(b"mffffffffffffffff,1"), "E01");
+        assert_eq!(client.request(b"Mffffffffffffffff,1:00"), "E01");
+        assert_eq!(client.request(b"qXfer:features:read:target.xml:ffffffffffffffff,ffffffffffffffff"), "l");
+    }
+
+    #[test]
+    fn non_hex_data_is_an_error() {
+        let mut client = Client::connect();
+        assert_eq!(client.request("M300,2:\u{e9}\u{e9}".as_bytes()), "E01");
+        assert_eq!(client.request(b"M300,2:\xff\xfe\xfd\xfc"), "E01");
+        assert_eq!(client.request("P0=\u{e9}".as_bytes()), "E01");
+        assert_eq!(client.request("G\u{e9}".as_bytes()), "E01");
+        assert_eq!(client.request("qRcmd,\u{e9}\u{e9}".as_bytes()), "E01");
+        assert_eq!(client.request(b"m300,1"), "00"); //nothing was written
+    }
+
+    #[test]
+    fn register_round_trip() {
+        let mut client = Client::connect();
+        assert_eq!(client.request(b"P3=7f"), "OK");
+        assert_eq!(client.request(b"p3"), "7f");
+        assert_eq!(client.request(b"P10=3412"), "OK"); //I is sent little-endian
+        assert_eq!(client.chip8.cpu.get_i(), 0x1234);
+        assert_eq!(client.request(b"g").len(), (16 + 2 + 2 + 1) * 2);
+    }
+}
@@ -0,0 +1,166 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+/// Anything that can hold down chip-8 keys.
+///
+/// Sources are polled once per event loop iteration and set the keys they are holding,
+/// the keyboard module combines all of them into the 16-key state the cpu sees.
+pub trait InputSource {
+    fn poll(&mut self, keys: &mut [bool; 16]);
+}
+
+//physical key to chip-8 key, the number row and the numpad both work
+const KEYMAP_HEX: [(VirtualKeyCode, u8); 26] = [
+    (VirtualKeyCode::Key0, 0x0), (VirtualKeyCode::Key1, 0x1), (VirtualKeyCode::Key2, 0x2), (VirtualKeyCode::Key3, 0x3),
+    (VirtualKeyCode::Key4, 0x4), (VirtualKeyCode::Key5, 0x5), (VirtualKeyCode::Key6, 0x6), (VirtualKeyCode::Key7, 0x7),
+    (VirtualKeyCode::Key8, 0x8), (VirtualKeyCode::Key9, 0x9), (VirtualKeyCode::A, 0xA), (VirtualKeyCode::B, 0xB),
+    (VirtualKeyCode::C, 0xC), (VirtualKeyCode::D, 0xD), (VirtualKeyCode::E, 0xE), (VirtualKeyCode::F, 0xF),
+    (VirtualKeyCode::Numpad0, 0x0), (VirtualKeyCode::Numpad1, 0x1), (VirtualKeyCode::Numpad2, 0x2), (VirtualKeyCode::Numpad3, 0x3),
+    (VirtualKeyCode::Numpad4, 0x4), (VirtualKeyCode::Numpad5, 0x5), (VirtualKeyCode::Numpad6, 0x6), (VirtualKeyCode::Numpad7, 0x7),
+    (VirtualKeyCode::Numpad8, 0x8), (VirtualKeyCode::Numpad9, 0x9)
+];
+
+//the VIP keypad laid over the left side of a qwerty keyboard
+//  1 2 3 C      1 2 3 4
+//  4 5 6 D  ->  Q W E R
+//  7 8 9 E      A S D F
+//  A 0 B F      Z X C V
+const KEYMAP_QWERTY: [(VirtualKeyCode, u8); 16] = [
+    (VirtualKeyCode::Key1, 0x1), (VirtualKeyCode::Key2, 0x2), (VirtualKeyCode::Key3, 0x3), (VirtualKeyCode::Key4, 0xC),
+    (VirtualKeyCode::Q, 0x4), (VirtualKeyCode::W, 0x5), (VirtualKeyCode::E, 0x6), (VirtualKeyCode::R, 0xD),
+    (VirtualKeyCode::A, 0x7), (VirtualKeyCode::S, 0x8), (VirtualKeyCode::D, 0x9), (VirtualKeyCode::F, 0xE),
+    (VirtualKeyCode::Z, 0xA), (VirtualKeyCode::X, 0x0), (VirtualKeyCode::C, 0xB), (VirtualKeyCode::V, 0xF)
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Keymap {
+    Hex,   //keys labelled 0-9 and A-F press the chip-8 key with the same label
+    Qwerty //keypad shape kept, 1234/QWER/ASDF/ZXCV
+}
+
+impl Keymap {
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Keymap::Hex => "HEX",
+            Keymap::Qwerty => "QWERTY"
+        }
+    }
+
+    fn get_bindings(&self) -> &'static [(VirtualKeyCode, u8)] {
+        match self {
+            Keymap::Hex => &KEYMAP_HEX,
+            Keymap::Qwerty => &KEYMAP_QWERTY
+        }
+    }
+
+}
+
+/// The host keyboard, read through winit.
+pub struct WinitKeyboard {
+    held: [bool; 16],
+    keymap: Keymap
+}
+
+impl WinitKeyboard {
+
+    pub fn new() -> Self {
+        Self {
+            held: [false; 16],
+            keymap: Keymap::Hex
+        }
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// Take in the latest winit input, call once per event loop iteration before polling.
+    pub fn update(&mut self, input: &WinitInputHelper) {
+        self.held = [false; 16];
+        for (key, key_addr) in self.keymap.get_bindings().iter() {
+            if input.key_pressed(*key) || input.key_held(*key) {
+                self.held[*key_addr as usize] = true;
+            }
+        }
+    }
+
+}
+
+impl InputSource for WinitKeyboard {
+    fn poll(&mut self, keys: &mut [bool; 16]) {
+        for (key, held) in keys.iter_mut().zip(self.held.iter()) {
+            *key |= *held;
+        }
+    }
+}
+
+/// Input device with no hardware behind it, keys are set from code.
+///
+/// Clones share the same key state, so one copy can be handed to the keyboard module
+/// while another is kept around to press keys with.
+#[derive(Clone)]
+pub struct VirtualDevice {
+    held: Rc<RefCell<[bool; 16]>>
+}
+
+impl VirtualDevice {
+
+    pub fn new() -> Self {
+        Self {
+            held: Rc::new(RefCell::new([false; 16]))
+        }
+    }
+
+    pub fn set_key(&self, key: u8, val: bool) {
+        self.held.borrow_mut()[key as usize] = val;
+    }
+
+    pub fn release_all(&self) {
+        *self.held.borrow_mut() = [false; 16];
+    }
+
+}
+
+impl InputSource for VirtualDevice {
+    fn poll(&mut self, keys: &mut [bool; 16]) {
+        for (key, held) in keys.iter_mut().zip(self.held.borrow().iter()) {
+            *key |= *held;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virtual_device_clones_share_keys() {
+        let device = VirtualDevice::new();
+        let mut source = device.clone();
+
+        device.set_key(0xA, true);
+        let mut keys = [false; 16];
+        source.poll(&mut keys);
+        assert!(keys[0xA]);
+        assert_eq!(keys.iter().filter(|key| **key).count(), 1);
+
+        device.release_all();
+        let mut keys = [false; 16];
+        source.poll(&mut keys);
+        assert!(!keys[0xA]);
+    }
+
+    #[test]
+    fn virtual_device_adds_to_other_sources() {
+        let device = VirtualDevice::new();
+        device.set_key(0x1, true);
+
+        let mut keys = [false; 16];
+        keys[0x2] = true; //held by a source polled earlier
+        device.clone().poll(&mut keys);
+        assert!(keys[0x1] && keys[0x2]);
+    }
+}
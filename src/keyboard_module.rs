@@ -1,18 +1,38 @@
-use winit::event::VirtualKeyCode;
 use winit_input_helper::WinitInputHelper;
 use crate::cpu::Cpu;
+use crate::movie::{MovieEvent, MovieRecorder};
+use crate::input_source::{InputSource, Keymap, WinitKeyboard};
 
 pub struct KeyboardModule {
-    keys: [bool; 16]
+    keys: [bool; 16],
+    recorder: Option<MovieRecorder>,
+    keyboard: WinitKeyboard,
+    sources: Vec<Box<dyn InputSource>> //gamepads, scripted input, ... on top of the keyboard
 }
 
 impl KeyboardModule {
     pub fn new() -> Self {
         KeyboardModule {
-            keys: [false; 16]
+            keys: [false; 16],
+            recorder: None,
+            keyboard: WinitKeyboard::new(),
+            sources: Vec::new()
         }
     }
 
+    pub fn add_source(&mut self, source: Box<dyn InputSource>) {
+        self.sources.push(source);
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keyboard.set_keymap(keymap);
+    }
+
+    //record every key change and key press from here on
+    pub fn set_recorder(&mut self, recorder: MovieRecorder) {
+        self.recorder = Some(recorder);
+    }
+
     pub fn set_key(&mut self, key: u8, val: bool) {
         self.keys[key as usize] = val;
     }
@@ -21,55 +41,147 @@ impl KeyboardModule {
         self.keys[key as usize]
     }
 
-    fn check_single_key(&mut self, input: &WinitInputHelper, cpu: &mut Cpu, key: VirtualKeyCode, key_addr: u8) {
+    pub fn get_keys(&self) -> [bool; 16] { self.keys }
+
+    /// Read the host keyboard and every other input source, then apply the combined key state.
+    pub fn check_keys(&mut self, input: &WinitInputHelper, cpu: &Cpu) {
+        self.keyboard.update(input);
+        self.poll_sources(cpu);
+    }
+
+    /// Combine the key state of all sources, any source holding a key holds it down.
+    /// The host keyboard keeps the state it had at the last `check_keys`.
+    pub fn poll_sources(&mut self, cpu: &Cpu) {
+
+        let mut held = [false; 16];
+        self.keyboard.poll(&mut held);
+        for source in self.sources.iter_mut() {
+            source.poll(&mut held);
+        }
+
+        for (key_addr, val) in held.iter().enumerate() {
+            self.record_key(cpu, key_addr as u8, *val);
+        }
+
+    }
 
-        if input.key_pressed(key) {
-            self.set_key(key_addr, true);
-            if cpu.get_wait() {
-                cpu.key_press(key_addr);
+    //set_key, but also writes the change to the movie if one is being recorded
+    fn record_key(&mut self, cpu: &Cpu, key_addr: u8, val: bool) {
+        if self.get_key(key_addr) != val {
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record(MovieEvent::Key { cycle: cpu.get_cycles(), key: key_addr, pressed: val });
             }
-        } else if input.key_held(key) {
-            self.set_key(key_addr, true);
-        } else if input.key_released(key) {
-            self.set_key(key_addr, false);
         }
+        self.set_key(key_addr, val);
+    }
+
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_module::DisplayModule;
+    use crate::input_source::VirtualDevice;
+    use crate::memory::Memory;
+    use crate::prng::{Prng, RngMode};
+    use crate::quirks::{KeyWaitQuirk, Quirks};
+    use crate::timer_module::TimerModule;
+
+    struct Machine {
+        cpu: Cpu,
+        memory: Memory,
+        display_module: DisplayModule,
+        timer_module: TimerModule,
+        keyboard_module: KeyboardModule,
+        device: VirtualDevice
     }
 
-    pub fn check_keys(&mut self, input: &WinitInputHelper, cpu: &mut Cpu) {
-
-        //regular keyboard
-        self.check_single_key(input, cpu, VirtualKeyCode::Key0, 0x0);
-        self.check_single_key(input, cpu, VirtualKeyCode::Key1, 0x1);
-        self.check_single_key(input, cpu, VirtualKeyCode::Key2, 0x2);
-        self.check_single_key(input, cpu, VirtualKeyCode::Key3, 0x3);
-        self.check_single_key(input, cpu, VirtualKeyCode::Key4, 0x4);
-        self.check_single_key(input, cpu, VirtualKeyCode::Key5, 0x5);
-        self.check_single_key(input, cpu, VirtualKeyCode::Key6, 0x6);
-        self.check_single_key(input, cpu, VirtualKeyCode::Key7, 0x7);
-        self.check_single_key(input, cpu, VirtualKeyCode::Key8, 0x8);
-        self.check_single_key(input, cpu, VirtualKeyCode::Key9, 0x9);
-        self.check_single_key(input, cpu, VirtualKeyCode::A, 0xA);
-        self.check_single_key(input, cpu, VirtualKeyCode::B, 0xB);
-        self.check_single_key(input, cpu, VirtualKeyCode::C, 0xC);
-        self.check_single_key(input, cpu, VirtualKeyCode::D, 0xD);
-        self.check_single_key(input, cpu, VirtualKeyCode::E, 0xE);
-        self.check_single_key(input, cpu, VirtualKeyCode::F, 0xF);
-
-        //numpad
-        self.check_single_key(input, cpu, VirtualKeyCode::Numpad0, 0x0);
-        self.check_single_key(input, cpu, VirtualKeyCode::Numpad1, 0x1);
-        self.check_single_key(input, cpu, VirtualKeyCode::Numpad2, 0x2);
-        self.check_single_key(input, cpu, VirtualKeyCode::Numpad3, 0x3);
-        self.check_single_key(input, cpu, VirtualKeyCode::Numpad4, 0x4);
-        self.check_single_key(input, cpu, VirtualKeyCode::Numpad5, 0x5);
-        self.check_single_key(input, cpu, VirtualKeyCode::Numpad6, 0x6);
-        self.check_single_key(input, cpu, VirtualKeyCode::Numpad7, 0x7);
-        self.check_single_key(input, cpu, VirtualKeyCode::Numpad8, 0x8);
-        self.check_single_key(input, cpu, VirtualKeyCode::Numpad9, 0x9);
+    impl Machine {
 
+        //F30A: LD V3, K then 1202: JP 202, so V3 gets the key and the cpu parks afterwards
+        fn new(key_wait: KeyWaitQuirk) -> Self {
+            let mut memory = Memory::new();
+            memory.load_rom(vec![0xF3, 0x0A, 0x12, 0x02]);
+            let device = VirtualDevice::new();
+            let mut keyboard_module = KeyboardModule::new();
+            keyboard_module.add_source(Box::new(device.clone()));
+            Self {
+                cpu: Cpu::new(Prng::new(RngMode::Xorshift, 1), Quirks { key_wait }),
+                memory,
+                display_module: DisplayModule::new(64, 32),
+                timer_module: TimerModule::new(),
+                keyboard_module,
+                device
+            }
+        }
+
+        fn set_key(&mut self, key: u8, val: bool) {
+            self.device.set_key(key, val);
+            self.keyboard_module.poll_sources(&self.cpu);
+        }
+
+        fn step(&mut self) {
+            self.cpu.execute_instruction(&mut self.memory, &mut self.display_module, &mut self.timer_module, &mut self.keyboard_module)
+                .expect("Error executing test program");
+        }
 
+    }
+
+    #[test]
+    fn get_keys_follows_virtual_device() {
+        let mut machine = Machine::new(KeyWaitQuirk::Release);
+
+        machine.set_key(0x5, true);
+        assert!(machine.keyboard_module.get_key(0x5));
+        assert_eq!(machine.keyboard_module.get_keys().iter().filter(|key| **key).count(), 1);
 
+        machine.set_key(0x5, false);
+        assert_eq!(machine.keyboard_module.get_keys(), [false; 16]);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn key_wait_release_completes_when_key_is_let_go() {
+        let mut machine = Machine::new(KeyWaitQuirk::Release);
+        machine.step(); //F30A starts waiting
+        assert_eq!(machine.cpu.get_pc(), 0x202);
+
+        machine.set_key(0x7, true);
+        machine.step();
+        machine.step();
+        assert_eq!(machine.cpu.get_reg(3), 0); //still held
+        assert_eq!(machine.cpu.get_pc(), 0x202);
+
+        machine.set_key(0x7, false);
+        machine.step();
+        assert_eq!(machine.cpu.get_reg(3), 0x7);
+        assert_eq!(machine.cpu.get_pc(), 0x202); //the completing cycle doesn't run an instruction
+
+        machine.step(); //JP 202
+        assert_eq!(machine.cpu.get_pc(), 0x202);
+    }
+
+    #[test]
+    fn key_wait_press_completes_on_key_down() {
+        let mut machine = Machine::new(KeyWaitQuirk::Press);
+        machine.step();
+
+        machine.set_key(0xC, true);
+        machine.step();
+        assert_eq!(machine.cpu.get_reg(3), 0xC);
+    }
+
+    #[test]
+    fn key_wait_ignores_key_held_before_the_wait() {
+        let mut machine = Machine::new(KeyWaitQuirk::Press);
+        machine.set_key(0x2, true);
+        machine.step(); //F30A starts waiting with 2 already down
+        machine.step();
+        assert_eq!(machine.cpu.get_reg(3), 0);
+
+        machine.set_key(0x2, false);
+        machine.step();
+        machine.set_key(0x2, true);
+        machine.step();
+        assert_eq!(machine.cpu.get_reg(3), 0x2);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::input_source::VirtualDevice;
+
+pub const KEYPAD_WIDTH: u32 = 32;
+
+const CELL_SIZE: usize = 8; //each key is an 8x8 block, so the 4x4 keypad is as tall as the 64x32 display
+
+//hex keypad as laid out on the COSMAC VIP
+const LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF]
+];
+
+//4x5 key labels, same shapes as the system font
+const LABELS: [[u8; 5]; 16] = [
+    [0xF0, 0x90, 0x90, 0x90, 0xF0], [0x20, 0x60, 0x20, 0x20, 0x70], [0xF0, 0x10, 0xF0, 0x80, 0xF0], [0xF0, 0x10, 0xF0, 0x10, 0xF0],
+    [0x90, 0x90, 0xF0, 0x10, 0x10], [0xF0, 0x80, 0xF0, 0x10, 0xF0], [0xF0, 0x80, 0xF0, 0x90, 0xF0], [0xF0, 0x10, 0x20, 0x40, 0x40],
+    [0xF0, 0x90, 0xF0, 0x90, 0xF0], [0xF0, 0x90, 0xF0, 0x10, 0xF0], [0xF0, 0x90, 0xF0, 0x90, 0x90], [0xE0, 0x90, 0xE0, 0x90, 0xE0],
+    [0xF0, 0x80, 0x80, 0x80, 0xF0], [0xE0, 0x90, 0x90, 0x90, 0xE0], [0xF0, 0x80, 0xF0, 0x80, 0xF0], [0xF0, 0x80, 0xF0, 0x80, 0x80]
+];
+
+const KEY_COLOR: [u8; 4] = [0x40, 0x40, 0x40, 0xFF];
+const KEY_PRESSED_COLOR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const LABEL_COLOR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const LABEL_PRESSED_COLOR: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+
+/// Clickable 4x4 hex keypad drawn to the right of the game.
+///
+/// Shows which keys the cpu currently sees as held, and holds down whichever key
+/// the left mouse button is pressed on through a virtual input device.
+pub struct KeypadOverlay {
+    x_offset: usize, //first pixel column of the keypad in the frame
+    device: VirtualDevice
+}
+
+impl KeypadOverlay {
+
+    pub fn new(x_offset: u32) -> Self {
+        Self {
+            x_offset: x_offset as usize,
+            device: VirtualDevice::new()
+        }
+    }
+
+    /// Input device that the mouse presses keys on, add it to the keyboard module as a source.
+    pub fn get_device(&self) -> VirtualDevice { self.device.clone() }
+
+    /// Update the mouse state, `pixel` is the cursor position in frame coordinates.
+    pub fn handle_mouse(&mut self, pixel: Option<(usize, usize)>, held: bool) {
+        self.device.release_all();
+        if let (Some(key), true) = (pixel.and_then(|pos| self.key_at(pos)), held) {
+            self.device.set_key(key, true);
+        }
+    }
+
+    fn key_at(&self, (x, y): (usize, usize)) -> Option<u8> {
+        if x < self.x_offset {
+            return None;
+        }
+        let col = (x - self.x_offset) / CELL_SIZE;
+        let row = y / CELL_SIZE;
+        LAYOUT.get(row).and_then(|keys| keys.get(col)).copied()
+    }
+
+    /// Draw the keypad into a frame that is `frame_width` pixels wide.
+    pub fn draw(&self, frame: &mut [u8], frame_width: usize, keys: &[bool; 16]) {
+
+        for (row, layout_row) in LAYOUT.iter().enumerate() {
+            for (col, key) in layout_row.iter().enumerate() {
+
+                let pressed = keys[*key as usize];
+                let (key_color, label_color) = if pressed { (KEY_PRESSED_COLOR, LABEL_PRESSED_COLOR) } else { (KEY_COLOR, LABEL_COLOR) };
+
+                //leave a 1 pixel gap on the right and bottom of each key
+                for cell_y in 0..(CELL_SIZE - 1) {
+                    for cell_x in 0..(CELL_SIZE - 1) {
+
+                        //label sits at (2, 1) inside the key
+                        let label_row = cell_y.checked_sub(1).and_then(|y| LABELS[*key as usize].get(y));
+                        let on_label = match (label_row, cell_x.checked_sub(2)) {
+                            (Some(bits), Some(label_x)) if label_x < 4 => (bits >> (7 - label_x)) & 1 != 0,
+                            _ => false
+                        };
+
+                        let x = self.x_offset + col * CELL_SIZE + cell_x;
+                        let y = row * CELL_SIZE + cell_y;
+                        let index = (y * frame_width + x) * 4;
+                        frame[index..index + 4].copy_from_slice(if on_label { &label_color } else { &key_color });
+                    }
+                }
+            }
+        }
+
+    }
+
+}
@@ -10,22 +10,70 @@ use std::env;
 
 mod cpu;
 mod memory;
+mod font;
+mod memory_map;
+mod sys_call;
 mod display_module;
 mod timer_module;
 mod keyboard_module;
+mod clock_module;
+mod options;
+mod prng;
+mod movie;
+mod quirks;
+mod input_source;
+mod keypad_overlay;
+mod memory_viewer;
+mod sprite_viewer;
+mod save_state;
+mod text;
+mod menu;
+mod chip8;
+mod cheats;
+mod opcode;
+mod platform;
+mod analyzer;
+mod coverage;
+mod profiler;
+mod symbols;
+mod trace;
+mod rom_watcher;
+mod gdb_stub;
+mod control_server;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+#[cfg(feature = "scripting")]
+mod scripting;
 
-use display_module::DisplayModule;
-use memory::Memory;
+use display_module::{DisplayModule, PALETTES};
 use cpu::Cpu;
-use timer_module::TimerModule;
-use crate::keyboard_module::KeyboardModule;
+use crate::chip8::Chip8;
+use crate::clock_module::{ClockModule, ClockRate};
+use crate::options::Options;
+use crate::prng::Prng;
+use crate::movie::{MovieHeader, MoviePlayer, MovieRecorder};
+use crate::keypad_overlay::{KeypadOverlay, KEYPAD_WIDTH};
+use crate::memory_viewer::{MemoryViewer, MEMORY_VIEWER_HEIGHT, MEMORY_VIEWER_WIDTH};
+use crate::sprite_viewer::{SpriteViewer, SPRITE_VIEWER_HEIGHT, SPRITE_VIEWER_WIDTH};
+use crate::menu::{Menu, MenuAction, Settings};
+use crate::input_source::Keymap;
+use crate::save_state::slot_filename;
+use crate::rom_watcher::RomWatcher;
+use crate::cheats::Cheat;
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+use crate::trace::TraceWriter;
+use crate::memory_map::{MemoryMap, PROGRAM_START};
+use crate::gdb_stub::GdbStub;
+use crate::control_server::ControlServer;
 
-const WIDTH: u32 = 64;
-const HEIGHT: u32 = 32;
 const START_SIZE_MULTIPLIER: u32 = 8;
 
-const TIMER_CLOCK_FREQ: u32 = 60;
-const DEFAULT_CPU_CLOCK: u64 = 1000;
+const WINDOW_TITLE: &str = "Chip8 Emulator";
+const FAST_FORWARD_KEY: VirtualKeyCode = VirtualKeyCode::Tab;
+const SLOW_MOTION_KEY: VirtualKeyCode = VirtualKeyCode::F2;
+const RESET_KEY: VirtualKeyCode = VirtualKeyCode::F5; //hard reset, shift for soft reset, ctrl to reload the ROM from disk
+const DEBUG_POLL_DELAY: Duration = Duration::from_millis(10); //how often to check the debugger and control sockets while halted
 
 
 
@@ -33,15 +81,49 @@ const DEFAULT_CPU_CLOCK: u64 = 1000;
 fn main() ->  Result<(), Error> {
 
     let args: Vec<String> = env::args().collect();
-    let filename: &String = &args[1]; //get filename form arguments
+    let mut options = Options::from_args(&args);
 
-    let cycle_freq: u64;
-    if args.len() == 3 {
-        cycle_freq = args[2].parse().expect("Error with command-line arguments");
-    } else {
-        cycle_freq = DEFAULT_CPU_CLOCK;
+    //a replayed movie dictates the settings it was recorded with
+    let mut movie_player = options.replay.as_ref().map(|filename| MoviePlayer::open(filename).expect("Error opening movie file."));
+    if let Some(player) = &movie_player {
+        let header = player.get_header();
+        options.seed = header.seed;
+        options.rng_mode = header.rng_mode;
+        options.clock_rate = header.clock_rate;
+        options.quirks = header.quirks;
+        if options.platform.is_some_and(|platform| platform != header.platform) {
+            panic!("Error: the movie was recorded on {}, it can't be replayed on another platform", header.platform.get_name());
+        }
+        options.platform = Some(header.platform);
+    }
+
+    //scan the ROM for the platform it was written for, and use that platform's quirks unless told otherwise
+    let program_start = options.platform.map_or(PROGRAM_START, |platform| platform.get_program_start());
+    let analysis = analyzer::analyze(&Memory::read_rom(&options.filename).expect("Error opening file."), program_start);
+    if options.analyze {
+        analysis.print_report();
+        return Ok(());
+    }
+    println!("Detected platform: {}", analysis.platform.get_name());
+    let platform = options.platform.unwrap_or(analysis.platform);
+    if options.platform.is_some() {
+        println!("Platform: {}", platform.get_name());
+    }
+    if !platform.is_emulated() {
+        println!("Warning: {} instructions are not supported, run with --analyze for details.", platform.get_name());
+    }
+    if !options.quirks_given && movie_player.is_none() {
+        options.quirks = platform.get_quirks();
+    }
+    let (width, height) = platform.get_display_size();
+    if options.memory_map.get_display_range().is_some_and(|range| (width * height / 8) as usize > range.len()) {
+        panic!("Error: the {} display doesn't fit in the VIP's display memory", platform.get_name());
+    }
+
+    match options.clock_rate {
+        ClockRate::Hz(freq) => println!("CPU Clock: {}Hz", freq),
+        ClockRate::InstructionsPerFrame(ipf) => println!("CPU Clock: {} instructions per frame", ipf)
     }
-    println!("CPU Clock: {}Hz", cycle_freq);
 
     //Init event loop
     let event_loop = EventLoop::new();
@@ -49,12 +131,24 @@ fn main() ->  Result<(), Error> {
     //init input helper
     let mut input = WinitInputHelper::new();
 
+    //the keypad overlay and debug panels get their own columns to the right of the game, in that order
+    let keypad_width = if options.keypad { KEYPAD_WIDTH } else { 0 };
+    let memory_viewer_width = if options.memory_viewer { MEMORY_VIEWER_WIDTH } else { 0 };
+    let frame_width = width + keypad_width + memory_viewer_width + if options.sprite_viewer { SPRITE_VIEWER_WIDTH } else { 0 };
+    let mut frame_height = height;
+    if options.memory_viewer {
+        frame_height = frame_height.max(MEMORY_VIEWER_HEIGHT);
+    }
+    if options.sprite_viewer {
+        frame_height = frame_height.max(SPRITE_VIEWER_HEIGHT);
+    }
+
     //init display window
     let window = {
-        let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
-        let start_size = LogicalSize::new((WIDTH as f64) * (START_SIZE_MULTIPLIER as f64), (HEIGHT as f64) * (START_SIZE_MULTIPLIER as f64));
+        let size = LogicalSize::new(frame_width as f64, frame_height as f64);
+        let start_size = LogicalSize::new((frame_width as f64) * (START_SIZE_MULTIPLIER as f64), (frame_height as f64) * (START_SIZE_MULTIPLIER as f64));
         WindowBuilder::new()
-            .with_title("Chip8 Emulator")
+            .with_title(WINDOW_TITLE)
             .with_inner_size(start_size)
             .with_min_inner_size(size)
             .build(&event_loop)
@@ -64,46 +158,158 @@ fn main() ->  Result<(), Error> {
     let mut pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(WIDTH, HEIGHT, surface_texture)?
+        Pixels::new(frame_width, frame_height, surface_texture)?
     };
 
     //init emulator components
-    let mut display_module = DisplayModule::new(WIDTH, HEIGHT);
-    let mut timer_module = TimerModule::new();
-    let mut memory = Memory::new();
-    let mut keyboard_module = KeyboardModule::new();
-    let mut cpu = Cpu::new();
-    memory.initialize(filename);
+    let cpu = Cpu::new(Prng::new(options.rng_mode, options.seed), options.quirks);
+    let mut chip8 = Chip8::new(cpu, DisplayModule::new(width, height), &options.filename);
+    chip8.memory.set_font(options.font.clone(), options.font_base);
+    chip8.memory.set_map(options.memory_map);
+    if options.memory_map != MemoryMap::flat() {
+        println!("Memory map: {}", options.memory_map.name);
+    }
+    println!("RNG seed: {}", chip8.cpu.get_rng_seed()); //pass with --seed to reproduce this run
+    if let Some(filename) = &options.symbols {
+        chip8.symbols = SymbolTable::load(filename).expect("Error loading symbol file.");
+    }
+    chip8.set_platform(platform);
+    chip8.cpu.set_sys_mode(options.sys_mode);
+    for (address, name) in options.sys_handlers.iter() {
+        let handler = sys_call::builtin(name).unwrap_or_else(|| panic!("Error: unknown sys handler {}", name));
+        chip8.cpu.register_sys_handler(*address, handler);
+    }
 
-    //init clocks
-    let cpu_clock_delay = Duration::from_nanos(1000000000 / cycle_freq);
-    let timer_clock_delay = Duration::from_nanos(1000000000 / TIMER_CLOCK_FREQ as u64); //should run at 60Hz
+    if let Some(filename) = &options.trace {
+        chip8.cpu.start_trace(TraceWriter::create(filename).expect("Error creating trace file."));
+    }
+    if options.coverage.is_some() {
+        chip8.cpu.enable_coverage();
+    }
+    if options.profile.is_some() {
+        chip8.cpu.enable_profiler();
+    }
 
-    let mut last_cpu_clock = Instant::checked_sub(&(Instant::now()), cpu_clock_delay).expect("timing initialization error. (cpu clock)");
-    let mut last_timer_clock = Instant::checked_sub(&(Instant::now()), timer_clock_delay).expect("timing initialization error. (timer clock)");
+    let mut keypad_overlay = if options.keypad { Some(KeypadOverlay::new(width)) } else { None };
+    if let Some(overlay) = &keypad_overlay {
+        chip8.keyboard_module.add_source(Box::new(overlay.get_device()));
+    }
+    let mut memory_viewer = if options.memory_viewer { Some(MemoryViewer::new(width + keypad_width)) } else { None };
+    let mut sprite_viewer = if options.sprite_viewer { Some(SpriteViewer::new(width + keypad_width + memory_viewer_width)) } else { None };
 
-    event_loop.run(move |event, _, control_flow| {
+    #[cfg(feature = "gamepad")]
+    {
+        let mapping = match &options.gamepad_map {
+            Some(filename) => gamepad::GamepadMapping::load(filename).expect("Error loading gamepad mapping."),
+            None => gamepad::GamepadMapping::default_mapping()
+        };
+        match gamepad::GamepadSource::new(mapping) {
+            Ok(source) => chip8.keyboard_module.add_source(Box::new(source)),
+            Err(e) => println!("Gamepads unavailable: {}", e)
+        }
+    }
+    #[cfg(not(feature = "gamepad"))]
+    {
+        if options.gamepad_map.is_some() {
+            println!("Warning: built without the gamepad feature, --gamepad-map is ignored.");
+        }
+    }
+
+    #[cfg(feature = "scripting")]
+    let mut script_host = options.script.as_ref().map(|filename| scripting::ScriptHost::load(filename).expect("Error loading script."));
+    #[cfg(feature = "scripting")]
+    {
+        if let Some(host) = &script_host {
+            chip8.keyboard_module.add_source(Box::new(host.get_device()));
+            chip8.memory.set_write_log(host.wants_memory_writes());
+        }
+    }
+    #[cfg(not(feature = "scripting"))]
+    {
+        if options.script.is_some() {
+            println!("Warning: built without the scripting feature, --script is ignored.");
+        }
+    }
+
+    if let Some(player) = &movie_player {
+        player.check_rom_hash(chip8.memory.get_rom_hash()).expect("Error replaying movie.");
+    }
+    if let Some(filename) = &options.record {
+        let header = MovieHeader {
+            rom_hash: chip8.memory.get_rom_hash(),
+            seed: chip8.cpu.get_rng_seed(),
+            rng_mode: chip8.cpu.get_rng_mode(),
+            clock_rate: options.clock_rate,
+            quirks: options.quirks,
+            platform: chip8.cpu.get_platform()
+        };
+        chip8.keyboard_module.set_recorder(MovieRecorder::create(filename, &header).expect("Error creating movie file."));
+    }
 
-        *control_flow = ControlFlow::Poll;
+    //init clock, runs the cpu in batches of cycles once per 60Hz frame
+    let mut clock_module = ClockModule::new(options.clock_rate, options.turbo_multiplier);
 
-        let now = Instant::now();
+    //pause menu, opened with escape
+    let mut settings = Settings {
+        clock_rate: options.clock_rate,
+        quirks: options.quirks,
+        palette: 0,
+        keymap: Keymap::Hex
+    };
+    let mut menu = Menu::new(settings);
 
-        if now.duration_since(last_cpu_clock) > cpu_clock_delay {
-            last_cpu_clock = now;
-            //execute cpu instruction
-            cpu.execute_instruction(&mut memory, &mut display_module, &mut timer_module, &mut keyboard_module);
-        }
+    let mut rom_watcher = options.watch.map(|mode| RomWatcher::new(mode, chip8.get_rom_filename()));
+
+    let mut gdb_stub = options.gdb_port.map(|port| GdbStub::bind(port).expect("Error starting GDB stub."));
+    let mut control_server = options.control_port.map(|port| ControlServer::bind(port).expect("Error starting control server."));
+    if let Some(server) = &control_server {
+        chip8.keyboard_module.add_source(Box::new(server.get_device()));
+    }
+
+    event_loop.run(move |event, _, control_flow| {
 
-        if now.duration_since(last_timer_clock) > timer_clock_delay {
-            last_timer_clock = now;
-            window.request_redraw();
-            //execute timer
-            timer_module.update();
+        if let Event::LoopDestroyed = event {
+            if let Err(e) = chip8.cpu.finish_trace() {
+                println!("Error writing trace: {}", e);
+            }
+            if let (Some(filename), Some(coverage)) = (&options.coverage, chip8.cpu.get_coverage()) {
+                match coverage.write_report(filename, chip8.get_rom_filename(), &chip8.memory, &analysis.code) {
+                    Ok(()) => println!("Wrote coverage report to {}", filename),
+                    Err(e) => println!("Error writing coverage report: {}", e)
+                }
+            }
+            if let (Some(filename), Some(profiler)) = (&options.profile, chip8.cpu.get_profiler()) {
+                print!("{}", profiler.get_summary());
+                match profiler.write_folded(filename) {
+                    Ok(()) => println!("Wrote profile to {}", filename),
+                    Err(e) => println!("Error writing profile: {}", e)
+                }
+            }
+            return;
         }
 
         // Draw the current frame
         if let Event::RedrawRequested(_) = event {
-            display_module.draw(pixels.get_frame());
+            chip8.display_module.draw(pixels.get_frame(), frame_width as usize);
+            if let Some(overlay) = &keypad_overlay {
+                overlay.draw(pixels.get_frame(), frame_width as usize, &chip8.keyboard_module.get_keys());
+            }
+            if let Some(viewer) = &mut memory_viewer {
+                viewer.update(&chip8.memory, Instant::now());
+                viewer.draw(pixels.get_frame(), frame_width as usize, &chip8.memory, &chip8.cpu, Instant::now());
+            }
+            if let Some(viewer) = &sprite_viewer {
+                viewer.draw(pixels.get_frame(), frame_width as usize, &chip8.memory, &chip8.cpu);
+            }
+            #[cfg(feature = "scripting")]
+            {
+                if let Some(host) = &script_host {
+                    host.draw(pixels.get_frame(), frame_width as usize);
+                }
+            }
+            if menu.is_open() {
+                menu.draw(pixels.get_frame(), frame_width as usize, width as usize, height as usize);
+            }
             if pixels
                 .render()
                 .map_err(|e| error!("pixels.render() failed: {:?}", e))
@@ -118,17 +324,220 @@ fn main() ->  Result<(), Error> {
         // Handle input events
         if input.update(&event) {
             // Close events
-            if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+            if input.quit() {
                 *control_flow = ControlFlow::Exit;
                 return;
             }
 
-            keyboard_module.check_keys(&input, &mut cpu);
-
             // Resize the window
             if let Some(size) = input.window_resized() {
                 pixels.resize(size.width, size.height);
             }
+
+            if let (Some(viewer), false) = (&mut sprite_viewer, menu.is_open()) {
+                let pixel = input.mouse().and_then(|pos| pixels.window_pos_to_pixel(pos).ok());
+                if viewer.handle_input(&input, pixel, &chip8.memory, &chip8.cpu, chip8.get_rom_filename()) {
+                    window.request_redraw();
+                }
+            }
+
+            //the emulator is paused while a byte is being edited in the memory viewer
+            if let (Some(viewer), false) = (&mut memory_viewer, menu.is_open()) {
+                let pixel = input.mouse().and_then(|pos| pixels.window_pos_to_pixel(pos).ok());
+                let used = viewer.handle_input(&input, pixel, &mut chip8.memory);
+                window.request_redraw();
+                if used {
+                    clock_module.resync(Instant::now());
+                    //once editing is over, carry on straight away rather than waiting for more input
+                    *control_flow = if viewer.is_editing() { ControlFlow::Wait } else { ControlFlow::WaitUntil(Instant::now()) };
+                    return;
+                }
+            }
+
+            //the emulator is paused while the menu is open, menu actions are carried out here
+            if menu.is_open() {
+                match menu.handle_input(&input) {
+                    MenuAction::None => {}
+                    MenuAction::Resume => {}
+                    MenuAction::Quit => {
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
+                    MenuAction::Reset => chip8.reset(),
+                    MenuAction::LoadRom(filename) => {
+                        match chip8.load_rom(&filename) {
+                            Ok(()) => println!("Loaded {}", filename),
+                            Err(e) => println!("Error loading {}: {}", filename, e)
+                        }
+                    }
+                    MenuAction::SaveState(slot) => {
+                        match chip8.save_state(&slot_filename(chip8.get_rom_filename(), slot)) {
+                            Ok(()) => println!("Saved state to slot {}", slot),
+                            Err(e) => println!("Error saving state: {}", e)
+                        }
+                    }
+                    MenuAction::LoadState(slot) => {
+                        match chip8.load_state(&slot_filename(chip8.get_rom_filename(), slot)) {
+                            Ok(()) => println!("Loaded state from slot {}", slot),
+                            Err(e) => println!("Error loading state: {}", e)
+                        }
+                    }
+                    MenuAction::ApplySettings(new_settings) => {
+                        settings = new_settings;
+                        clock_module.set_rate(settings.clock_rate);
+                        chip8.cpu.set_quirks(settings.quirks);
+                        chip8.display_module.set_palette(PALETTES[settings.palette]);
+                        chip8.keyboard_module.set_keymap(settings.keymap);
+                    }
+                    MenuAction::StartSearch => chip8.ram_search.start(&chip8.memory),
+                    MenuAction::FilterSearch(filter) => chip8.ram_search.filter(&chip8.memory, filter, None),
+                    MenuAction::AddCheat(address, value) => {
+                        let cheat = Cheat { address, value, enabled: true, name: String::new() };
+                        if let Err(e) = chip8.cheats.add(cheat) {
+                            println!("Error saving cheats: {}", e);
+                        }
+                    }
+                    MenuAction::ToggleCheat(index) => {
+                        let enabled = chip8.cheats.get_cheats().get(index).is_some_and(|cheat| !cheat.enabled);
+                        if let Err(e) = chip8.cheats.set_enabled(index, enabled) {
+                            println!("Error saving cheats: {}", e);
+                        }
+                    }
+                }
+                menu.set_cheat_info(chip8.ram_search.get_results(&chip8.memory), chip8.cheats.get_cheats());
+
+                window.request_redraw();
+                if menu.is_open() {
+                    *control_flow = ControlFlow::Wait;
+                    return;
+                }
+                clock_module.resync(Instant::now()); //don't catch up on the time spent in the menu
+            } else if input.key_pressed(VirtualKeyCode::Escape) {
+                menu.open(chip8.get_rom_filename(), settings);
+                menu.set_cheat_info(chip8.ram_search.get_results(&chip8.memory), chip8.cheats.get_cheats());
+                menu.set_call_stack(chip8.get_backtrace().lines().map(|line| line.to_string()).collect());
+                window.request_redraw();
+                *control_flow = ControlFlow::Wait;
+                return;
+            }
+
+            //clicking on the keypad overlay holds the key under the cursor
+            if let Some(overlay) = &mut keypad_overlay {
+                let pixel = input.mouse().and_then(|pos| pixels.window_pos_to_pixel(pos).ok());
+                overlay.handle_mouse(pixel, input.mouse_held(0));
+            }
+
+            //physical input is ignored while a movie is playing
+            if movie_player.is_none() {
+                chip8.keyboard_module.check_keys(&input, &chip8.cpu);
+            }
+
+            //pick up a ROM that was rebuilt while we were running
+            if let Some(watcher) = &mut rom_watcher {
+                if watcher.poll(chip8.get_rom_filename(), Instant::now()) {
+                    match chip8.hot_reload(watcher.get_mode()) {
+                        Ok(()) => println!("{} changed on disk, reloaded", chip8.get_rom_filename()),
+                        Err(e) => println!("Error reloading {}: {}", chip8.get_rom_filename(), e)
+                    }
+                }
+            }
+
+            if input.key_pressed(RESET_KEY) {
+                if input.held_control() {
+                    match chip8.reload_rom() {
+                        Ok(()) => println!("Reloaded {}", chip8.get_rom_filename()),
+                        Err(e) => println!("Error reloading {}: {}", chip8.get_rom_filename(), e)
+                    }
+                } else if input.held_shift() {
+                    chip8.soft_reset();
+                } else {
+                    chip8.reset();
+                }
+            }
+
+            //while a debugger or script has the cpu stopped, only its commands move the program along
+            let cycles = chip8.cpu.get_cycles();
+            if let Some(stub) = &mut gdb_stub {
+                stub.poll(&mut chip8);
+            }
+            let control_handled = control_server.as_mut().is_some_and(|server| server.poll(&mut chip8, &mut clock_module));
+            #[cfg(feature = "scripting")]
+            {
+                //steps taken from there don't run the script hooks, drop the writes they logged
+                chip8.memory.take_writes();
+            }
+            if control_handled || chip8.cpu.get_cycles() != cycles {
+                window.request_redraw();
+            }
+            let halted = gdb_stub.as_ref().is_some_and(|stub| stub.is_halted()) || control_server.as_ref().is_some_and(|server| server.is_paused());
+            if halted {
+                clock_module.resync(Instant::now());
+                *control_flow = ControlFlow::WaitUntil(Instant::now() + DEBUG_POLL_DELAY);
+                return;
+            }
+
+            //speed controls, hold to fast forward, press to step through slow motion speeds
+            let speed_label = clock_module.get_speed_label();
+            clock_module.set_fast_forward(input.key_held(FAST_FORWARD_KEY));
+            if input.key_pressed(SLOW_MOTION_KEY) {
+                clock_module.toggle_slow_motion();
+            }
+            if clock_module.get_speed_label() != speed_label {
+                match clock_module.get_speed_label() {
+                    Some(label) => window.set_title(&format!("{} [{}]", WINDOW_TITLE, label)),
+                    None => window.set_title(WINDOW_TITLE)
+                }
+            }
+
+            //run every frame that is due, each frame is a batch of cpu cycles followed by a timer tick
+            let frames = clock_module.frames_due(Instant::now());
+            'frames: for _ in 0..frames {
+                for _ in 0..clock_module.cycles_for_frame() {
+                    if gdb_stub.as_mut().is_some_and(|stub| stub.check_breakpoint(&chip8)) {
+                        break 'frames;
+                    }
+                    if let Some(player) = &mut movie_player {
+                        player.apply(&chip8.cpu, &mut chip8.keyboard_module);
+                    }
+                    #[cfg(feature = "scripting")]
+                    {
+                        if let Some(host) = &mut script_host {
+                            host.before_step(&mut chip8);
+                        }
+                    }
+                    chip8.step();
+                    #[cfg(feature = "scripting")]
+                    {
+                        if let Some(host) = &mut script_host {
+                            host.after_step(&mut chip8);
+                        }
+                    }
+                }
+                chip8.tick_frame();
+                #[cfg(feature = "scripting")]
+                {
+                    if let Some(host) = &mut script_host {
+                        host.after_frame(&mut chip8);
+                    }
+                }
+            }
+            if frames > 0 {
+                window.request_redraw();
+            }
+
+            if movie_player.as_ref().is_some_and(|player| player.is_finished()) {
+                println!("Replay finished at cycle {}, input is live again.", chip8.cpu.get_cycles());
+                movie_player = None;
+            }
+
+            //a breakpoint may have been hit above, and sockets need checking even if frames are far apart
+            *control_flow = if gdb_stub.as_ref().is_some_and(|stub| stub.is_halted()) {
+                ControlFlow::WaitUntil(Instant::now() + DEBUG_POLL_DELAY)
+            } else if gdb_stub.is_some() || control_server.is_some() {
+                ControlFlow::WaitUntil(clock_module.next_frame().min(Instant::now() + DEBUG_POLL_DELAY))
+            } else {
+                ControlFlow::WaitUntil(clock_module.next_frame())
+            };
         }
 
     });
@@ -1,8 +1,21 @@
 use std::fs::File;
 use std::io::Read;
+use std::ops::Range;
+use crate::font::Font;
+use crate::memory_map::{MemoryMap, PROGRAM_START};
+use crate::save_state::{StateReader, StateWriter};
 
+#[derive(Clone)]
 pub struct Memory {
-    mem: Vec<u8>
+    mem: Vec<u8>,
+    rom: Vec<u8>, //ROM image as read from disk, copied back in on reset
+    rom_hash: u64,
+    font: Font,
+    font_base: u16,
+    map: MemoryMap,
+    program_start: u16, //where the ROM is loaded, depends on the platform
+    write_log: Option<Vec<u16>>, //addresses written since the last take_writes, when enabled
+    display_written: bool        //the display region changed since the last take_display_written
 }
 
 
@@ -10,151 +23,196 @@ impl Memory {
 
     pub fn new() -> Self {
         Self {
-            mem: vec![0; 4096]
+            mem: vec![0; 4096],
+            rom: Vec::new(),
+            rom_hash: 0,
+            font: Font::default(),
+            font_base: 0,
+            map: MemoryMap::default(),
+            program_start: PROGRAM_START,
+            write_log: None,
+            display_written: false
         }
     }
 
-    pub fn initialize(&mut self, filename: &String) { 
+    pub fn initialize(&mut self, filename: &str) {
+        let rom = Self::read_rom(filename).expect("Error opening file.");
+        self.load_rom(rom);
+    }
+
+    /// Read a ROM image from disk, anything past the end of memory is ignored.
+    pub fn read_rom(filename: &str) -> std::io::Result<Vec<u8>> {
+
+        let mut file = File::open(filename)?;
+        let mut buf: [u8; 0xE00] = [0;0xE00]; //create buffer of max file size (total memory size 4096 - system files 512 == 3584 == 0xE00
+
+        let num_bytes = file.read(&mut buf)?;
+
+        Ok(buf[..num_bytes].to_vec())
+    }
+
+    /// Replace the loaded ROM image and reset memory with it.
+    pub fn load_rom(&mut self, rom: Vec<u8>) {
+        self.replace_rom(rom);
+        self.reset();
+    }
+
+    /// Swap in a new ROM image without touching memory, `restore_rom` copies it in.
+    pub fn replace_rom(&mut self, rom: Vec<u8>) {
+        self.rom_hash = Self::hash_rom(&rom);
+        self.rom = rom;
+        self.check_rom_fits();
+    }
+
+    /// Put memory back to its power-on state: font, loaded ROM and zeroes everywhere else.
+    pub fn reset(&mut self) {
 
         //initialize the array to all zeroes
         self.mem = vec![0; 4096];
 
-        //set first 80 bytes of memory to font files
         self.init_font();
 
-        let mut file = File::open(filename).expect("Error opening file.");
-        let mut buf: [u8; 0xE00] = [0;0xE00]; //create buffer of max file size (total memory size 4096 - system files 512 == 3584 == 0xE00
+        self.restore_rom();
 
-        let num_bytes = file.read(&mut buf).expect("error reading from file.");
+        self.display_written = true;
 
-        for n in 0..num_bytes {
-            self.mem[0x200 + n] = buf[n];
+    }
+
+    /// Copy the loaded ROM image back to the program start, leaving the rest of memory alone.
+    pub fn restore_rom(&mut self) {
+        let start = self.program_start as usize;
+        let len = self.rom.len().min(self.mem.len() - start); //a ROM read for 0x200 can be a page too long for 0x300
+        self.mem[start..start + len].copy_from_slice(&self.rom[..len]);
+    }
+
+    pub fn get_program_start(&self) -> u16 { self.program_start }
+
+    /// Load ROMs at `start` from now on, takes effect on the next reset.
+    pub fn set_program_start(&mut self, start: u16) {
+        self.program_start = start;
+        self.check_rom_fits();
+    }
+
+    pub fn get_map(&self) -> MemoryMap { self.map }
+
+    pub fn set_map(&mut self, map: MemoryMap) {
+        self.map = map;
+        self.check_rom_fits();
+    }
+
+    //a ROM running into the interpreter's work area would overwrite its stack or the display
+    fn check_rom_fits(&self) {
+        let rom_end = self.program_start as usize + self.rom.len();
+        if rom_end > self.mem.len() {
+            println!("Warning: the ROM runs {} bytes past the end of memory, they are left out", rom_end - self.mem.len());
+        }
+        if let Some(work_area) = self.map.work_area {
+            if rom_end > work_area as usize {
+                println!("Warning: the ROM overlaps the interpreter's work area at {:03X}", work_area);
+            }
         }
+    }
 
+    //64 bit FNV-1a, stable between builds so it can be written to movie files
+    fn hash_rom(rom: &[u8]) -> u64 {
+        let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+        for byte in rom {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+        hash
     }
 
-    fn init_font(&mut self) { //loads system font into memory, this is expected by all chip-8 ROMS
-
-        //0
-        self.mem[0] = 0xF0;
-        self.mem[1] = 0x90;
-        self.mem[2] = 0x90;
-        self.mem[3] = 0x90;
-        self.mem[4] = 0xF0;
-
-        //1
-        self.mem[5] = 0x20;
-        self.mem[6] = 0x60;
-        self.mem[7] = 0x20;
-        self.mem[8] = 0x20;
-        self.mem[9] = 0x70;
-
-        //2
-        self.mem[10] = 0xF0;
-        self.mem[11] = 0x10;
-        self.mem[12] = 0xF0;
-        self.mem[13] = 0x80;
-        self.mem[14] = 0xF0;
-
-        //3
-        self.mem[15] = 0xF0;
-        self.mem[16] = 0x10;
-        self.mem[17] = 0xF0;
-        self.mem[18] = 0x80;
-        self.mem[19] = 0xF0;
-
-        //4
-        self.mem[20] = 0x90;
-        self.mem[21] = 0x90;
-        self.mem[22] = 0xF0;
-        self.mem[23] = 0x10;
-        self.mem[24] = 0x10;
-
-        //5
-        self.mem[25] = 0xF0;
-        self.mem[26] = 0x80;
-        self.mem[27] = 0xF0;
-        self.mem[28] = 0x10;
-        self.mem[29] = 0xF0;
-
-        //6
-        self.mem[30] = 0xF0;
-        self.mem[31] = 0x80;
-        self.mem[32] = 0xF0;
-        self.mem[33] = 0x90;
-        self.mem[34] = 0xF0;
-
-        //7
-        self.mem[35] = 0xF0;
-        self.mem[36] = 0x10;
-        self.mem[37] = 0x20;
-        self.mem[38] = 0x40;
-        self.mem[39] = 0x40;
-
-        //8
-        self.mem[40] = 0xF0;
-        self.mem[41] = 0x90;
-        self.mem[42] = 0xF0;
-        self.mem[43] = 0x90;
-        self.mem[44] = 0xF0;
-
-        //9
-        self.mem[45] = 0xF0;
-        self.mem[46] = 0x90;
-        self.mem[47] = 0xF0;
-        self.mem[48] = 0x10;
-        self.mem[49] = 0xF0;
-
-        //A
-        self.mem[50] = 0xF0;
-        self.mem[51] = 0x90;
-        self.mem[52] = 0xF0;
-        self.mem[53] = 0x90;
-        self.mem[54] = 0x90;
-
-        //B
-        self.mem[55] = 0xE0;
-        self.mem[56] = 0x90;
-        self.mem[57] = 0xE0;
-        self.mem[58] = 0x90;
-        self.mem[59] = 0xE0;
-
-        //C
-        self.mem[60] = 0xF0;
-        self.mem[61] = 0x80;
-        self.mem[62] = 0x80;
-        self.mem[63] = 0x80;
-        self.mem[64] = 0xF0;
-
-        //D
-        self.mem[65] = 0xE0;
-        self.mem[66] = 0x90;
-        self.mem[67] = 0x90;
-        self.mem[68] = 0x90;
-        self.mem[69] = 0xE0;
-
-        //E
-        self.mem[70] = 0xF0;
-        self.mem[71] = 0x80;
-        self.mem[72] = 0xF0;
-        self.mem[73] = 0x80;
-        self.mem[74] = 0xF0;
-
-        //F
-        self.mem[75] = 0xF0;
-        self.mem[76] = 0x80;
-        self.mem[77] = 0xF0;
-        self.mem[78] = 0x80;
-        self.mem[79] = 0x80
+    pub fn get_rom_hash(&self) -> u64 { self.rom_hash }
+    pub fn get_rom_size(&self) -> usize { self.rom.len() }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_bytes(&self.mem);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        let len = self.mem.len();
+        self.mem.copy_from_slice(reader.get_bytes(len)?);
+        Ok(())
+    }
+
+    //loads the font into memory, this is expected by all chip-8 ROMS
+    fn init_font(&mut self) {
+        let base = self.font_base as usize;
+        let data = self.font.get_data();
+        self.mem[base..base + data.len()].copy_from_slice(&data);
+    }
+
+    /// Replace the font and where it is stored, then reset memory so the old one is gone.
+    pub fn set_font(&mut self, font: Font, base: u16) {
+        if base as usize + font.get_data().len() > self.mem.len() {
+            panic!("Error: font at {:03X} doesn't fit in memory", base);
+        }
+        self.font = font;
+        self.font_base = base;
+        self.reset();
+    }
+
+    /// Address of the 4x5 glyph for the low nibble of `digit`, for Fx29.
+    pub fn get_font_address(&self, digit: u8) -> u16 {
+        self.font_base + self.font.get_glyph_offset(digit)
+    }
+
+    /// Addresses the font occupies.
+    pub fn get_font_range(&self) -> Range<usize> {
+        let base = self.font_base as usize;
+        base..base + self.font.get_data().len()
     }
 
     pub fn set_memory(&mut self, address: u16, val: u8) {
         self.mem[address as usize] = val;
+        if let Some(log) = &mut self.write_log {
+            log.push(address);
+        }
+        if self.map.get_display_range().is_some_and(|range| range.contains(&address)) {
+            self.display_written = true;
+        }
+    }
+
+    /// Mirror a byte of the screen into the display region. Not a write by the program,
+    /// so it isn't logged and doesn't count as a display change.
+    pub fn store_display(&mut self, address: u16, val: u8) {
+        self.mem[address as usize] = val;
+    }
+
+    /// Whether the display region was written since the last call.
+    pub fn take_display_written(&mut self) -> bool {
+        std::mem::take(&mut self.display_written)
     }
 
     pub fn get_memory(&self, address: u16) -> u8 {
         self.mem[address as usize]
     }
 
+    pub fn get_size(&self) -> usize { self.mem.len() }
+    pub fn get_all(&self) -> &[u8] { &self.mem }
+
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    /// Lend the whole memory array out without copying it, memory is empty until `put_all` returns it.
+    pub fn take_all(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.mem)
+    }
+
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    pub fn put_all(&mut self, mem: Vec<u8>) {
+        self.mem = mem;
+    }
+
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    /// Start or stop keeping a list of the addresses written with set_memory.
+    pub fn set_write_log(&mut self, enabled: bool) {
+        self.write_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    /// Addresses written since the last call, oldest first.
+    pub fn take_writes(&mut self) -> Vec<u16> {
+        self.write_log.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
 }
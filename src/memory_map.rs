@@ -0,0 +1,60 @@
+//! Where an interpreter keeps its own data in the 4 KB address space.
+
+use std::ops::Range;
+
+pub const PROGRAM_START: u16 = 0x200;
+
+const VIP_WORK_AREA: u16 = 0xEA0;   //stack, interpreter variables, registers and display up to the end of memory
+const VIP_STACK_TOP: u16 = 0xED0;   //return addresses are pushed down from here, 0xEA0-0xECF
+const VIP_DISPLAY: u16 = 0xF00;     //64x32 display, 8 bytes per row
+const VIP_DISPLAY_SIZE: u16 = 0x100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryMap {
+    pub name: &'static str,
+    pub work_area: Option<u16>,       //start of the interpreter's area at the top of memory, programs should stay below it
+    pub stack_top: Option<u16>,       //the cpu stack is kept in memory, entry n is the 2 bytes at stack_top - 2n
+    pub display_address: Option<u16>  //the display is kept in memory, 1 bit per pixel with the leftmost pixel in the top bit
+}
+
+impl MemoryMap {
+
+    /// The interpreter's data lives outside the address space and programs have everything from 0x200 up.
+    pub fn flat() -> Self {
+        Self {
+            name: "flat",
+            work_area: None,
+            stack_top: None,
+            display_address: None
+        }
+    }
+
+    /// The 4 KB COSMAC VIP: the interpreter sits below 0x200 and keeps its stack and the display
+    /// in 0xEA0-0xFFF. Keeping the stack or the display in that memory is optional, so programs
+    /// that read or write them can see the same values as on the VIP.
+    pub fn vip(stack_in_memory: bool, display_in_memory: bool) -> Self {
+        Self {
+            name: "vip",
+            work_area: Some(VIP_WORK_AREA),
+            stack_top: if stack_in_memory { Some(VIP_STACK_TOP) } else { None },
+            display_address: if display_in_memory { Some(VIP_DISPLAY) } else { None }
+        }
+    }
+
+    /// Addresses of the display, if it is kept in memory.
+    pub fn get_display_range(&self) -> Option<Range<u16>> {
+        self.display_address.map(|address| address..address + VIP_DISPLAY_SIZE)
+    }
+
+    /// Address of stack entry `n` (1 is the oldest), if the stack is kept in memory.
+    pub fn get_stack_address(&self, n: u8) -> Option<u16> {
+        self.stack_top.map(|top| top - 2 * n as u16)
+    }
+
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self::flat()
+    }
+}
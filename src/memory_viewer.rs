@@ -0,0 +1,246 @@
+use std::time::{Duration, Instant};
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::memory_map::PROGRAM_START;
+use crate::text::{draw_text, fill_rect, CHAR_WIDTH, LINE_HEIGHT};
+
+pub const MEMORY_VIEWER_WIDTH: u32 = 120;
+pub const MEMORY_VIEWER_HEIGHT: u32 = 60;
+
+const BYTES_PER_ROW: usize = 8;
+const ROWS: usize = MEMORY_VIEWER_HEIGHT as usize / LINE_HEIGHT - 1; //the first line is the header
+const HEX_X: usize = 3 * CHAR_WIDTH + 1;                  //after the 3 digit address
+const BYTE_WIDTH: usize = 2 * CHAR_WIDTH + 1;             //two digits and a 1 pixel gap
+const ASCII_X: usize = HEX_X + BYTES_PER_ROW * BYTE_WIDTH + 2;
+const WRITE_HIGHLIGHT: Duration = Duration::from_secs(1); //how long a changed byte stays highlighted
+
+const BACKGROUND: [u8; 4] = [0x10, 0x10, 0x10, 0xFF];
+const FONT_BACKGROUND: [u8; 4] = [0x10, 0x20, 0x50, 0xFF];
+const ROM_BACKGROUND: [u8; 4] = [0x10, 0x38, 0x18, 0xFF];
+const PC_BACKGROUND: [u8; 4] = [0xE0, 0xC0, 0x20, 0xFF];
+const I_BACKGROUND: [u8; 4] = [0x20, 0xB0, 0xD0, 0xFF];
+const SELECTED_BACKGROUND: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const TEXT_COLOR: [u8; 4] = [0xC0, 0xC0, 0xC0, 0xFF];
+const HEADER_COLOR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const WRITTEN_COLOR: [u8; 4] = [0xFF, 0x60, 0x40, 0xFF];
+const HIGHLIGHT_TEXT_COLOR: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+
+const HEX_KEYS: [(VirtualKeyCode, VirtualKeyCode, u8); 10] = [
+    (VirtualKeyCode::Key0, VirtualKeyCode::Numpad0, 0x0), (VirtualKeyCode::Key1, VirtualKeyCode::Numpad1, 0x1),
+    (VirtualKeyCode::Key2, VirtualKeyCode::Numpad2, 0x2), (VirtualKeyCode::Key3, VirtualKeyCode::Numpad3, 0x3),
+    (VirtualKeyCode::Key4, VirtualKeyCode::Numpad4, 0x4), (VirtualKeyCode::Key5, VirtualKeyCode::Numpad5, 0x5),
+    (VirtualKeyCode::Key6, VirtualKeyCode::Numpad6, 0x6), (VirtualKeyCode::Key7, VirtualKeyCode::Numpad7, 0x7),
+    (VirtualKeyCode::Key8, VirtualKeyCode::Numpad8, 0x8), (VirtualKeyCode::Key9, VirtualKeyCode::Numpad9, 0x9)
+];
+const HEX_LETTER_KEYS: [(VirtualKeyCode, u8); 6] = [
+    (VirtualKeyCode::A, 0xA), (VirtualKeyCode::B, 0xB), (VirtualKeyCode::C, 0xC),
+    (VirtualKeyCode::D, 0xD), (VirtualKeyCode::E, 0xE), (VirtualKeyCode::F, 0xF)
+];
+
+/// Hex and ASCII view of memory drawn to the right of the game.
+///
+/// PC and I are highlighted, as are the font and ROM regions, and bytes that changed in the last second
+/// are drawn in red. The mouse wheel and Page Up/Down scroll. Clicking a byte pauses the game to edit it:
+/// type two hex digits to replace it, the arrow keys move, Enter or Escape resumes.
+pub struct MemoryViewer {
+    x_offset: usize,                //first pixel column of the panel in the frame
+    scroll: usize,                  //first row shown
+    selected: Option<usize>,        //address being edited, the game is paused while there is one
+    high_nibble: Option<u8>,        //first digit typed for the selected byte
+    snapshot: Vec<u8>,              //memory as of the last update, to spot writes
+    written: Vec<Option<Instant>>   //when each byte last changed
+}
+
+impl MemoryViewer {
+
+    pub fn new(x_offset: u32) -> Self {
+        Self {
+            x_offset: x_offset as usize,
+            scroll: PROGRAM_START as usize / BYTES_PER_ROW,
+            selected: None,
+            high_nibble: None,
+            snapshot: Vec::new(),
+            written: Vec::new()
+        }
+    }
+
+    pub fn is_editing(&self) -> bool { self.selected.is_some() }
+
+    /// Handle scrolling, clicks and typing. `pixel` is the cursor position in frame coordinates.
+    /// Returns true if the input was used by the viewer and shouldn't reach the game.
+    pub fn handle_input(&mut self, input: &WinitInputHelper, pixel: Option<(usize, usize)>, memory: &mut Memory) -> bool {
+
+        let rows = memory.get_size() / BYTES_PER_ROW;
+        let hovered = pixel.is_some_and(|(x, _)| (self.x_offset..self.x_offset + MEMORY_VIEWER_WIDTH as usize).contains(&x));
+        if hovered && input.scroll_diff() != 0.0 {
+            self.scroll_by(rows, if input.scroll_diff() > 0.0 { -1 } else { 1 });
+        }
+        if input.key_pressed(VirtualKeyCode::PageUp) {
+            self.scroll_by(rows, -(ROWS as isize));
+        } else if input.key_pressed(VirtualKeyCode::PageDown) {
+            self.scroll_by(rows, ROWS as isize);
+        }
+
+        if input.mouse_pressed(0) {
+            if let Some(address) = pixel.and_then(|pos| self.address_at(pos)).filter(|address| *address < memory.get_size()) {
+                self.selected = Some(address);
+                self.high_nibble = None;
+                return true;
+            }
+        }
+
+        let selected = match self.selected {
+            Some(selected) => selected,
+            None => return false
+        };
+
+        if input.key_pressed(VirtualKeyCode::Escape) || input.key_pressed(VirtualKeyCode::Return) {
+            self.selected = None;
+        } else if input.key_pressed(VirtualKeyCode::Left) {
+            self.select(selected.wrapping_sub(1), memory.get_size());
+        } else if input.key_pressed(VirtualKeyCode::Right) {
+            self.select(selected + 1, memory.get_size());
+        } else if input.key_pressed(VirtualKeyCode::Up) {
+            self.select(selected.wrapping_sub(BYTES_PER_ROW), memory.get_size());
+        } else if input.key_pressed(VirtualKeyCode::Down) {
+            self.select(selected + BYTES_PER_ROW, memory.get_size());
+        } else if let Some(digit) = hex_digit_pressed(input) {
+            match self.high_nibble.take() {
+                None => self.high_nibble = Some(digit),
+                Some(high) => {
+                    memory.set_memory(selected as u16, high << 4 | digit);
+                    self.select(selected + 1, memory.get_size());
+                }
+            }
+        }
+        true
+    }
+
+    fn scroll_by(&mut self, rows: usize, delta: isize) {
+        let max_scroll = rows.saturating_sub(ROWS) as isize;
+        self.scroll = (self.scroll as isize + delta).clamp(0, max_scroll) as usize;
+    }
+
+    //move the edit cursor, ignoring moves off either end of memory, and keep it on screen
+    fn select(&mut self, address: usize, size: usize) {
+        if address >= size {
+            return;
+        }
+        self.selected = Some(address);
+        self.high_nibble = None;
+        let row = address / BYTES_PER_ROW;
+        if row < self.scroll {
+            self.scroll = row;
+        } else if row >= self.scroll + ROWS {
+            self.scroll = row + 1 - ROWS;
+        }
+    }
+
+    //byte under a pixel, in either the hex or the ASCII column
+    fn address_at(&self, (x, y): (usize, usize)) -> Option<usize> {
+        let x = x.checked_sub(self.x_offset).filter(|x| *x < MEMORY_VIEWER_WIDTH as usize)?;
+        let row = (y / LINE_HEIGHT).checked_sub(1).filter(|row| *row < ROWS)?;
+        let column = if (HEX_X..HEX_X + BYTES_PER_ROW * BYTE_WIDTH).contains(&x) {
+            (x - HEX_X) / BYTE_WIDTH
+        } else if (ASCII_X..ASCII_X + BYTES_PER_ROW * CHAR_WIDTH).contains(&x) {
+            (x - ASCII_X) / CHAR_WIDTH
+        } else {
+            return None;
+        };
+        Some((self.scroll + row) * BYTES_PER_ROW + column)
+    }
+
+    /// Note which bytes changed since the last update, call before drawing.
+    pub fn update(&mut self, memory: &Memory, now: Instant) {
+        let bytes = memory.get_all();
+        if self.snapshot.len() != bytes.len() {
+            self.snapshot = bytes.to_vec();
+            self.written = vec![None; bytes.len()];
+            return;
+        }
+        let display = memory.get_map().get_display_range().map(|range| range.start as usize..range.end as usize).unwrap_or_default();
+        for (address, (old, new)) in self.snapshot.iter_mut().zip(bytes.iter()).enumerate() {
+            if old != new {
+                *old = *new;
+                if !display.contains(&address) {
+                    self.written[address] = Some(now); //the display changes all the time, it would drown out real writes
+                }
+            }
+        }
+    }
+
+    /// Draw the panel into a frame that is `frame_width` pixels wide.
+    pub fn draw(&self, frame: &mut [u8], frame_width: usize, memory: &Memory, cpu: &Cpu, now: Instant) {
+
+        let clip_width = self.x_offset + MEMORY_VIEWER_WIDTH as usize;
+        fill_rect(frame, frame_width, self.x_offset, 0, MEMORY_VIEWER_WIDTH as usize, MEMORY_VIEWER_HEIGHT as usize, BACKGROUND);
+
+        let header = match self.selected {
+            Some(address) => format!("PC {:03X} I {:03X} EDIT {:03X}", cpu.get_pc(), cpu.get_i(), address),
+            None => format!("PC {:03X} I {:03X}", cpu.get_pc(), cpu.get_i())
+        };
+        draw_text(frame, frame_width, clip_width, self.x_offset + 1, 0, &header, HEADER_COLOR);
+
+        let pc = cpu.get_pc() as usize;
+        let rom_start = memory.get_program_start() as usize;
+        let rom_end = rom_start + memory.get_rom_size();
+        let font_range = memory.get_font_range();
+        for row in 0..ROWS {
+            let row_address = (self.scroll + row) * BYTES_PER_ROW;
+            if row_address >= memory.get_size() {
+                break;
+            }
+            let y = (row + 1) * LINE_HEIGHT;
+            draw_text(frame, frame_width, clip_width, self.x_offset, y, &format!("{:03X}", row_address), HEADER_COLOR);
+
+            for column in 0..BYTES_PER_ROW {
+                let address = row_address + column;
+                let value = memory.get_memory(address as u16);
+
+                let highlight = if self.selected == Some(address) {
+                    Some(SELECTED_BACKGROUND)
+                } else if address == pc || address == pc + 1 {
+                    Some(PC_BACKGROUND)
+                } else if address == cpu.get_i() as usize {
+                    Some(I_BACKGROUND)
+                } else {
+                    None
+                };
+                let background = highlight.unwrap_or(if font_range.contains(&address) {
+                    FONT_BACKGROUND
+                } else if (rom_start..rom_end).contains(&address) {
+                    ROM_BACKGROUND
+                } else {
+                    BACKGROUND
+                });
+                let written = self.written.get(address).copied().flatten().is_some_and(|time| now.duration_since(time) < WRITE_HIGHLIGHT);
+                let color = if highlight.is_some() { HIGHLIGHT_TEXT_COLOR } else if written { WRITTEN_COLOR } else { TEXT_COLOR };
+
+                let hex = match (self.selected == Some(address), self.high_nibble) {
+                    (true, Some(high)) => format!("{:X}_", high),
+                    _ => format!("{:02X}", value)
+                };
+                let hex_x = self.x_offset + HEX_X + column * BYTE_WIDTH;
+                fill_rect(frame, frame_width, hex_x, y, BYTE_WIDTH, LINE_HEIGHT, background);
+                draw_text(frame, frame_width, clip_width, hex_x + 1, y, &hex, color);
+
+                let ascii = if (0x20..0x7F).contains(&value) { value as char } else { '.' };
+                let ascii_x = self.x_offset + ASCII_X + column * CHAR_WIDTH;
+                fill_rect(frame, frame_width, ascii_x, y, CHAR_WIDTH, LINE_HEIGHT, background);
+                draw_text(frame, frame_width, clip_width, ascii_x, y, &ascii.to_string(), color);
+            }
+        }
+
+    }
+
+}
+
+fn hex_digit_pressed(input: &WinitInputHelper) -> Option<u8> {
+    HEX_KEYS.iter()
+        .find(|(key, numpad_key, _)| input.key_pressed(*key) || input.key_pressed(*numpad_key))
+        .map(|(_, _, digit)| *digit)
+        .or_else(|| HEX_LETTER_KEYS.iter().find(|(key, _)| input.key_pressed(*key)).map(|(_, digit)| *digit))
+}
@@ -0,0 +1,350 @@
+use std::fs;
+use std::path::Path;
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+use crate::cheats::{Cheat, SearchFilter};
+use crate::clock_module::ClockRate;
+use crate::display_module::PALETTES;
+use crate::input_source::Keymap;
+use crate::quirks::{KeyWaitQuirk, Quirks};
+use crate::save_state::slot_filename;
+use crate::text::{draw_text, fill_rect, LINE_HEIGHT};
+
+const SAVE_SLOTS: u8 = 10;
+const HZ_STEP: u64 = 100;
+const MAX_LABEL_LEN: usize = 15; //one character of margin on the 64 pixel wide display
+
+const MAIN_ITEMS: [&str; 9] = ["RESUME", "ROMS", "RESET", "SAVE STATE", "LOAD STATE", "CHEATS", "CALL STACK", "SETTINGS", "QUIT"];
+//first items of the cheats screen, followed by the result count and the cheat list
+const SEARCH_ITEMS: [(&str, Option<SearchFilter>); 5] = [
+    ("NEW SEARCH", None),
+    ("SAME", Some(SearchFilter::Equal)),
+    ("CHANGED", Some(SearchFilter::Changed)),
+    ("UP", Some(SearchFilter::Increased)),
+    ("DOWN", Some(SearchFilter::Decreased))
+];
+
+/// User adjustable settings, edited in the menu and applied by the main loop.
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    pub clock_rate: ClockRate,
+    pub quirks: Quirks,
+    pub palette: usize, //index into PALETTES
+    pub keymap: Keymap
+}
+
+/// What the main loop should do in response to menu input.
+pub enum MenuAction {
+    None,
+    Resume,
+    Quit,
+    Reset,
+    LoadRom(String),
+    SaveState(u8),
+    LoadState(u8),
+    ApplySettings(Settings),
+    StartSearch,
+    FilterSearch(SearchFilter),
+    AddCheat(u16, u8),
+    ToggleCheat(usize)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Screen {
+    Main,
+    Roms,
+    Save,
+    Load,
+    Cheats,
+    Results,
+    CallStack,
+    Settings
+}
+
+/// Pause menu drawn over the game.
+///
+/// Up and down move the cursor, Enter picks an item, left and right change settings
+/// and Escape goes back a screen (or closes the menu from the main screen).
+/// The cheats screen runs a RAM search, picking one of its results freezes that address as a cheat.
+/// The call stack screen lists PC and the call sites of the routines the program is in.
+pub struct Menu {
+    open: bool,
+    screen: Screen,
+    selected: usize,
+    scroll: usize,
+    rom_filename: String,
+    roms: Vec<String>, //paths of the files in the current ROM's directory
+    search_results: Vec<(u16, u8)>,
+    cheats: Vec<Cheat>,
+    call_stack: Vec<String>,
+    settings: Settings
+}
+
+impl Menu {
+
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            open: false,
+            screen: Screen::Main,
+            selected: 0,
+            scroll: 0,
+            rom_filename: String::new(),
+            roms: Vec::new(),
+            search_results: Vec::new(),
+            cheats: Vec::new(),
+            call_stack: Vec::new(),
+            settings
+        }
+    }
+
+    pub fn is_open(&self) -> bool { self.open }
+
+    /// Open the main screen. `rom_filename` is the running ROM, used to find its directory and save slots.
+    pub fn open(&mut self, rom_filename: &str, settings: Settings) {
+        self.open = true;
+        self.rom_filename = rom_filename.to_string();
+        self.settings = settings;
+        self.show(Screen::Main);
+    }
+
+    /// RAM search results and cheats to list, call on opening and after each cheat action.
+    pub fn set_cheat_info(&mut self, search_results: Vec<(u16, u8)>, cheats: &[Cheat]) {
+        self.search_results = search_results;
+        self.cheats = cheats.to_vec();
+    }
+
+    /// Backtrace lines for the call stack screen, call on opening.
+    pub fn set_call_stack(&mut self, call_stack: Vec<String>) {
+        self.call_stack = call_stack;
+    }
+
+    fn show(&mut self, screen: Screen) {
+        self.screen = screen;
+        self.selected = 0;
+        self.scroll = 0;
+        if screen == Screen::Roms {
+            self.roms = list_roms(&self.rom_filename);
+        }
+    }
+
+    pub fn handle_input(&mut self, input: &WinitInputHelper) -> MenuAction {
+
+        let item_count = self.get_items().len().max(1);
+
+        if input.key_pressed(VirtualKeyCode::Escape) {
+            match self.screen {
+                Screen::Main => {
+                    self.open = false;
+                    return MenuAction::Resume;
+                }
+                Screen::Results => self.show(Screen::Cheats),
+                _ => self.show(Screen::Main)
+            }
+        } else if input.key_pressed(VirtualKeyCode::Up) {
+            self.selected = (self.selected + item_count - 1) % item_count;
+        } else if input.key_pressed(VirtualKeyCode::Down) {
+            self.selected = (self.selected + 1) % item_count;
+        } else if input.key_pressed(VirtualKeyCode::Left) && self.screen == Screen::Settings {
+            return self.change_setting(false);
+        } else if input.key_pressed(VirtualKeyCode::Right) && self.screen == Screen::Settings {
+            return self.change_setting(true);
+        } else if input.key_pressed(VirtualKeyCode::Return) {
+            return self.select();
+        }
+
+        MenuAction::None
+    }
+
+    fn select(&mut self) -> MenuAction {
+        match self.screen {
+            Screen::Main => match MAIN_ITEMS[self.selected] {
+                "RESUME" => {
+                    self.open = false;
+                    return MenuAction::Resume;
+                }
+                "ROMS" => self.show(Screen::Roms),
+                "RESET" => {
+                    self.open = false;
+                    return MenuAction::Reset;
+                }
+                "SAVE STATE" => self.show(Screen::Save),
+                "LOAD STATE" => self.show(Screen::Load),
+                "CHEATS" => self.show(Screen::Cheats),
+                "CALL STACK" => self.show(Screen::CallStack),
+                "SETTINGS" => self.show(Screen::Settings),
+                _ => return MenuAction::Quit
+            },
+            Screen::Roms => {
+                if let Some(rom) = self.roms.get(self.selected) {
+                    self.open = false;
+                    return MenuAction::LoadRom(rom.clone());
+                }
+            }
+            Screen::Save => {
+                self.open = false;
+                return MenuAction::SaveState(self.selected as u8);
+            }
+            Screen::Load => {
+                self.open = false;
+                return MenuAction::LoadState(self.selected as u8);
+            }
+            Screen::Cheats => {
+                if let Some((_, filter)) = SEARCH_ITEMS.get(self.selected) {
+                    return match filter {
+                        Some(filter) => MenuAction::FilterSearch(*filter),
+                        None => MenuAction::StartSearch
+                    };
+                } else if self.selected == SEARCH_ITEMS.len() {
+                    self.show(Screen::Results);
+                } else {
+                    return MenuAction::ToggleCheat(self.selected - SEARCH_ITEMS.len() - 1);
+                }
+            }
+            Screen::Results => {
+                if let Some((address, value)) = self.search_results.get(self.selected) {
+                    return MenuAction::AddCheat(*address, *value);
+                }
+            }
+            Screen::CallStack => {}
+            Screen::Settings => return self.change_setting(true)
+        }
+        MenuAction::None
+    }
+
+    fn change_setting(&mut self, forward: bool) -> MenuAction {
+        let settings = &mut self.settings;
+        match self.selected {
+            0 => {
+                settings.clock_rate = match settings.clock_rate {
+                    ClockRate::Hz(freq) if forward => ClockRate::Hz(freq + HZ_STEP),
+                    ClockRate::Hz(freq) => ClockRate::Hz(freq.saturating_sub(HZ_STEP).max(HZ_STEP)),
+                    ClockRate::InstructionsPerFrame(ipf) if forward => ClockRate::InstructionsPerFrame(ipf + 1),
+                    ClockRate::InstructionsPerFrame(ipf) => ClockRate::InstructionsPerFrame(ipf.saturating_sub(1).max(1))
+                };
+            }
+            1 => {
+                settings.quirks.key_wait = match settings.quirks.key_wait {
+                    KeyWaitQuirk::Press => KeyWaitQuirk::Release,
+                    KeyWaitQuirk::Release => KeyWaitQuirk::Press
+                };
+            }
+            2 => {
+                settings.palette = if forward {
+                    (settings.palette + 1) % PALETTES.len()
+                } else {
+                    (settings.palette + PALETTES.len() - 1) % PALETTES.len()
+                };
+            }
+            _ => {
+                settings.keymap = match settings.keymap {
+                    Keymap::Hex => Keymap::Qwerty,
+                    Keymap::Qwerty => Keymap::Hex
+                };
+            }
+        }
+        MenuAction::ApplySettings(self.settings)
+    }
+
+    fn get_items(&self) -> Vec<String> {
+        match self.screen {
+            Screen::Main => MAIN_ITEMS.iter().map(|item| item.to_string()).collect(),
+            Screen::Roms => self.roms.iter()
+                .map(|rom| Path::new(rom).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default())
+                .collect(),
+            Screen::Save | Screen::Load => (0..SAVE_SLOTS)
+                .map(|slot| {
+                    let used = Path::new(&slot_filename(&self.rom_filename, slot)).exists();
+                    format!("SLOT {}{}", slot, if used { " *" } else { "" })
+                })
+                .collect(),
+            Screen::Cheats => SEARCH_ITEMS.iter()
+                .map(|(label, _)| label.to_string())
+                .chain(std::iter::once(format!("RESULTS {}", self.search_results.len())))
+                .chain(self.cheats.iter().map(|cheat| {
+                    format!("{:03X}={:02X} {} {}", cheat.address, cheat.value, if cheat.enabled { "ON" } else { "OFF" }, cheat.name)
+                }))
+                .collect(),
+            Screen::Results => self.search_results.iter()
+                .map(|(address, value)| format!("{:03X} {:02X}", address, value))
+                .collect(),
+            Screen::CallStack => self.call_stack.clone(),
+            Screen::Settings => {
+                let speed = match self.settings.clock_rate {
+                    ClockRate::Hz(freq) => format!("HZ <{}>", freq),
+                    ClockRate::InstructionsPerFrame(ipf) => format!("IPF <{}>", ipf)
+                };
+                let key_wait = match self.settings.quirks.key_wait {
+                    KeyWaitQuirk::Press => "WAIT <PRESS>",
+                    KeyWaitQuirk::Release => "WAIT <RELEASE>"
+                };
+                vec![
+                    speed,
+                    key_wait.to_string(),
+                    format!("COLOR <{}>", PALETTES[self.settings.palette].name),
+                    format!("KEYS <{}>", self.settings.keymap.get_name())
+                ]
+            }
+        }
+    }
+
+    /// Draw the menu over the left `width` x `height` pixels of the frame, with the colors of the current palette.
+    pub fn draw(&mut self, frame: &mut [u8], frame_width: usize, width: usize, height: usize) {
+
+        let palette = PALETTES[self.settings.palette];
+        let [fr, fg, fb] = palette.foreground;
+        let [br, bg, bb] = palette.background;
+        let foreground = [fr, fg, fb, 0xFF];
+        let background = [br, bg, bb, 0xFF];
+
+        fill_rect(frame, frame_width, 0, 0, width, height, background);
+
+        //keep the selected line on screen
+        let visible_lines = height / LINE_HEIGHT;
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + visible_lines {
+            self.scroll = self.selected + 1 - visible_lines;
+        }
+
+        let items = self.get_items();
+        if items.is_empty() {
+            draw_text(frame, frame_width, width, 1, 1, "EMPTY", foreground);
+        }
+
+        for (line, item) in items.iter().enumerate().skip(self.scroll).take(visible_lines) {
+            let y = (line - self.scroll) * LINE_HEIGHT;
+            let label: String = item.chars().take(MAX_LABEL_LEN).collect();
+            if line == self.selected {
+                fill_rect(frame, frame_width, 0, y, width, LINE_HEIGHT, foreground);
+                draw_text(frame, frame_width, width, 1, y + 1, &label, background);
+            } else {
+                draw_text(frame, frame_width, width, 1, y + 1, &label, foreground);
+            }
+        }
+    }
+
+}
+
+//every file next to the running ROM, except hidden files and save states
+fn list_roms(rom_filename: &str) -> Vec<String> {
+
+    let dir = Path::new(rom_filename).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+
+    let mut roms: Vec<String> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .filter(|path| {
+                let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+                !name.starts_with('.') && !name.contains(".state")
+            })
+            .map(|path| path.to_string_lossy().to_string())
+            .collect(),
+        Err(_) => Vec::new()
+    };
+
+    roms.sort();
+    roms
+}
@@ -0,0 +1,314 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+
+use crate::clock_module::ClockRate;
+use crate::cpu::Cpu;
+use crate::keyboard_module::KeyboardModule;
+use crate::platform::Platform;
+use crate::prng::RngMode;
+use crate::quirks::{KeyWaitQuirk, Quirks};
+
+const MOVIE_MAGIC: &str = "c8movie";
+const MOVIE_VERSION: u32 = 4; //2: key events are raw presses and releases, Fx0A waits inside the cpu. 3: the platform is recorded. 4: the rng mode is recorded
+
+/// Everything needed to start a run in the same state it was recorded in.
+#[derive(Clone, Copy, Debug)]
+pub struct MovieHeader {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub rng_mode: RngMode,
+    pub clock_rate: ClockRate,
+    pub quirks: Quirks,
+    pub platform: Platform
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MovieEvent {
+    Key { cycle: u64, key: u8, pressed: bool } //KeyboardModule::set_key changed a key
+}
+
+impl MovieEvent {
+    fn get_cycle(&self) -> u64 {
+        match *self {
+            MovieEvent::Key { cycle, .. } => cycle
+        }
+    }
+}
+
+/// Writes input events to a movie file as they happen.
+///
+/// Movie files are plain text, a header followed by one event per line tagged with the cpu cycle
+/// it happened on. Every line is flushed right away so a crash still leaves a usable movie.
+pub struct MovieRecorder {
+    writer: BufWriter<File>
+}
+
+impl MovieRecorder {
+
+    pub fn create(filename: &str, header: &MovieHeader) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+
+        writeln!(writer, "{} {}", MOVIE_MAGIC, MOVIE_VERSION)?;
+        writeln!(writer, "rom {:016X}", header.rom_hash)?;
+        writeln!(writer, "seed {}", header.seed)?;
+        writeln!(writer, "platform {}", header.platform.get_option_name())?;
+        match header.rng_mode {
+            RngMode::Xorshift => writeln!(writer, "rng xorshift")?,
+            RngMode::Vip => writeln!(writer, "rng vip")?
+        }
+        match header.clock_rate {
+            ClockRate::Hz(freq) => writeln!(writer, "clock hz {}", freq)?,
+            ClockRate::InstructionsPerFrame(ipf) => writeln!(writer, "clock ipf {}", ipf)?
+        }
+        match header.quirks.key_wait {
+            KeyWaitQuirk::Press => writeln!(writer, "quirk key_wait press")?,
+            KeyWaitQuirk::Release => writeln!(writer, "quirk key_wait release")?
+        }
+        writer.flush()?;
+
+        Ok(Self { writer })
+    }
+
+    pub fn record(&mut self, event: MovieEvent) {
+        let result = match event {
+            MovieEvent::Key { cycle, key, pressed } => writeln!(self.writer, "key {} {:X} {}", cycle, key, pressed as u8)
+        };
+        result.and_then(|_| self.writer.flush()).expect("error writing movie file.");
+    }
+
+}
+
+/// Plays back a recorded movie, handing out events as the cpu reaches their cycle.
+pub struct MoviePlayer {
+    header: MovieHeader,
+    events: Vec<MovieEvent>,
+    next: usize
+}
+
+impl MoviePlayer {
+
+    pub fn open(filename: &str) -> std::io::Result<Self> {
+        let reader = BufReader::new(File::open(filename)?);
+        let mut lines = reader.lines();
+
+        let first_line = lines.next().transpose()?.unwrap_or_default();
+        match first_line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            [MOVIE_MAGIC, version] if *version == MOVIE_VERSION.to_string() => {}
+            [MOVIE_MAGIC, version] => return Err(invalid(&format!("movie version {} can't be replayed, this build reads version {}", version, MOVIE_VERSION))),
+            _ => return Err(invalid("not a movie file"))
+        }
+
+        let mut rom_hash = None;
+        let mut seed = None;
+        let mut rng_mode = None;
+        let mut clock_rate = None;
+        let mut platform = None;
+        let mut quirks = Quirks::default();
+        let mut events = Vec::new();
+
+        for line in lines {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => {}
+                ["rom", hash] => rom_hash = Some(u64::from_str_radix(hash, 16).map_err(|_| invalid(&line))?),
+                ["seed", value] => seed = Some(value.parse().map_err(|_| invalid(&line))?),
+                ["platform", name] => platform = Some(Platform::from_name(name).ok_or_else(|| invalid(&line))?),
+                ["rng", "xorshift"] => rng_mode = Some(RngMode::Xorshift),
+                ["rng", "vip"] => rng_mode = Some(RngMode::Vip),
+                ["clock", "hz", value] => clock_rate = Some(ClockRate::Hz(value.parse().map_err(|_| invalid(&line))?)),
+                ["clock", "ipf", value] => clock_rate = Some(ClockRate::InstructionsPerFrame(value.parse().map_err(|_| invalid(&line))?)),
+                ["quirk", "key_wait", "press"] => quirks.key_wait = KeyWaitQuirk::Press,
+                ["quirk", "key_wait", "release"] => quirks.key_wait = KeyWaitQuirk::Release,
+                ["key", cycle, key, pressed] => events.push(MovieEvent::Key {
+                    cycle: cycle.parse().map_err(|_| invalid(&line))?,
+                    key: parse_key(key).ok_or_else(|| invalid(&line))?,
+                    pressed: *pressed == "1"
+                }),
+                _ => return Err(invalid(&line))
+            }
+        }
+
+        let header = MovieHeader {
+            rom_hash: rom_hash.ok_or_else(|| invalid("missing rom hash"))?,
+            seed: seed.ok_or_else(|| invalid("missing seed"))?,
+            rng_mode: rng_mode.ok_or_else(|| invalid("missing rng mode"))?,
+            clock_rate: clock_rate.ok_or_else(|| invalid("missing clock rate"))?,
+            quirks,
+            platform: platform.ok_or_else(|| invalid("missing platform"))?
+        };
+
+        Ok(Self { header, events, next: 0 })
+    }
+
+    pub fn get_header(&self) -> &MovieHeader { &self.header }
+
+    /// A movie only replays in sync on the ROM it was recorded with.
+    pub fn check_rom_hash(&self, rom_hash: u64) -> std::io::Result<()> {
+        if self.header.rom_hash != rom_hash {
+            return Err(invalid("recorded with a different ROM"));
+        }
+        Ok(())
+    }
+
+    /// Feed every event that is due by the cpu's current cycle into the keyboard.
+    /// Call before each instruction.
+    pub fn apply(&mut self, cpu: &Cpu, keyboard_module: &mut KeyboardModule) {
+        while let Some(event) = self.events.get(self.next) {
+            if event.get_cycle() > cpu.get_cycles() {
+                break;
+            }
+            match *event {
+                MovieEvent::Key { key, pressed, .. } => keyboard_module.set_key(key, pressed)
+            }
+            self.next += 1;
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+
+}
+
+fn parse_key(key: &str) -> Option<u8> {
+    u8::from_str_radix(key, 16).ok().filter(|key| *key < 16)
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid movie file: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_module::DisplayModule;
+    use crate::input_source::VirtualDevice;
+    use crate::memory::Memory;
+    use crate::prng::Prng;
+    use crate::timer_module::TimerModule;
+
+    //F30A: LD V3, K, 7401: ADD V4, 1, C5FF: RND V5, FF, A300: LD I, 300, F555: LD [I], V5, 1200: JP 200
+    const ROM: [u8; 12] = [0xF3, 0x0A, 0x74, 0x01, 0xC5, 0xFF, 0xA3, 0x00, 0xF5, 0x55, 0x12, 0x00];
+    const FRAMES: u64 = 12;
+    const CYCLES_PER_FRAME: u64 = 7;
+
+    struct Machine {
+        cpu: Cpu,
+        memory: Memory,
+        display_module: DisplayModule,
+        timer_module: TimerModule,
+        keyboard_module: KeyboardModule
+    }
+
+    impl Machine {
+
+        fn new(header: &MovieHeader) -> Self {
+            let mut memory = Memory::new();
+            memory.load_rom(ROM.to_vec());
+            Self {
+                cpu: Cpu::new(Prng::new(header.rng_mode, header.seed), header.quirks),
+                memory,
+                display_module: DisplayModule::new(64, 32),
+                timer_module: TimerModule::new(),
+                keyboard_module: KeyboardModule::new()
+            }
+        }
+
+        fn step(&mut self) {
+            self.cpu.execute_instruction(&mut self.memory, &mut self.display_module, &mut self.timer_module, &mut self.keyboard_module)
+                .expect("Error executing test program");
+        }
+
+        fn tick_frame(&mut self) {
+            self.timer_module.update();
+            self.cpu.tick_frame();
+        }
+
+    }
+
+    fn rom_hash() -> u64 {
+        let mut memory = Memory::new();
+        memory.load_rom(ROM.to_vec());
+        memory.get_rom_hash()
+    }
+
+    fn header() -> MovieHeader {
+        MovieHeader {
+            rom_hash: rom_hash(),
+            seed: 42,
+            rng_mode: RngMode::Vip,
+            clock_rate: ClockRate::InstructionsPerFrame(CYCLES_PER_FRAME as u32),
+            quirks: Quirks::default(),
+            platform: Platform::Chip8
+        }
+    }
+
+    fn movie_filename(name: &str) -> String {
+        std::env::temp_dir().join(format!("c8emu_movie_test_{}_{}.txt", name, std::process::id())).to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn replay_matches_recording() {
+        let filename = movie_filename("round_trip");
+        let header = header();
+
+        let mut recorded = Machine::new(&header);
+        let device = VirtualDevice::new();
+        recorded.keyboard_module.add_source(Box::new(device.clone()));
+        recorded.keyboard_module.set_recorder(MovieRecorder::create(&filename, &header).expect("Error creating movie file"));
+        for frame in 0..FRAMES {
+            device.set_key((frame % 3) as u8 + 1, frame % 2 == 0);
+            device.set_key((frame % 3) as u8 + 4, frame % 4 == 1);
+            recorded.keyboard_module.poll_sources(&recorded.cpu);
+            for _ in 0..CYCLES_PER_FRAME {
+                recorded.step();
+            }
+            recorded.tick_frame();
+        }
+
+        let mut player = MoviePlayer::open(&filename).expect("Error opening movie file");
+        std::fs::remove_file(&filename).expect("Error removing movie file");
+        player.check_rom_hash(rom_hash()).expect("Error checking rom hash");
+        let mut replayed = Machine::new(player.get_header());
+        for _ in 0..FRAMES {
+            for _ in 0..CYCLES_PER_FRAME {
+                player.apply(&replayed.cpu, &mut replayed.keyboard_module);
+                replayed.step();
+            }
+            replayed.tick_frame();
+        }
+
+        assert!(player.is_finished());
+        assert!(recorded.cpu.get_reg(4) > 1); //the key waits completed more than once
+        for x in 0..16 {
+            assert_eq!(replayed.cpu.get_reg(x), recorded.cpu.get_reg(x), "V{:X}", x);
+        }
+        assert_eq!(replayed.cpu.get_pc(), recorded.cpu.get_pc());
+        assert_eq!(replayed.cpu.get_i(), recorded.cpu.get_i());
+        assert_eq!(replayed.memory.get_all(), recorded.memory.get_all());
+    }
+
+    #[test]
+    fn other_version_is_rejected() {
+        let filename = movie_filename("version");
+        std::fs::write(&filename, format!("{} {}\nrom 0\n", MOVIE_MAGIC, MOVIE_VERSION - 1)).expect("Error writing movie file");
+        let result = MoviePlayer::open(&filename);
+        std::fs::remove_file(&filename).expect("Error removing movie file");
+
+        let message = result.err().expect("old movie was accepted").to_string();
+        assert!(message.contains("can't be replayed"), "{}", message);
+    }
+
+    #[test]
+    fn other_rom_is_rejected() {
+        let filename = movie_filename("rom_hash");
+        drop(MovieRecorder::create(&filename, &header()).expect("Error creating movie file"));
+        let player = MoviePlayer::open(&filename).expect("Error opening movie file");
+        std::fs::remove_file(&filename).expect("Error removing movie file");
+
+        assert!(player.check_rom_hash(rom_hash()).is_ok());
+        assert!(player.check_rom_hash(rom_hash().wrapping_add(1)).is_err());
+    }
+
+}
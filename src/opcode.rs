@@ -0,0 +1,226 @@
+use std::fmt;
+
+use crate::platform::Platform;
+
+/// A decoded instruction. Covers CHIP-8 plus the SUPER-CHIP and XO-CHIP extensions,
+/// so tools can recognise them even though the cpu only runs CHIP-8.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    Sys(u16),               //0nnn
+    Cls,                    //00E0
+    Ret,                    //00EE
+    Jp(u16),                //1nnn
+    Call(u16),              //2nnn
+    SeByte(u8, u8),         //3xkk
+    SneByte(u8, u8),        //4xkk
+    SeReg(u8, u8),          //5xy0
+    LdByte(u8, u8),         //6xkk
+    AddByte(u8, u8),        //7xkk
+    LdReg(u8, u8),          //8xy0
+    Or(u8, u8),             //8xy1
+    And(u8, u8),            //8xy2
+    Xor(u8, u8),            //8xy3
+    AddReg(u8, u8),         //8xy4
+    Sub(u8, u8),            //8xy5
+    Shr(u8, u8),            //8xy6
+    Subn(u8, u8),           //8xy7
+    Shl(u8, u8),            //8xyE
+    SneReg(u8, u8),         //9xy0
+    LdI(u16),               //Annn
+    JpV0(u16),              //Bnnn
+    Rnd(u8, u8),            //Cxkk
+    Drw(u8, u8, u8),        //Dxyn
+    Skp(u8),                //Ex9E
+    Sknp(u8),               //ExA1
+    LdVxDt(u8),             //Fx07
+    LdVxK(u8),              //Fx0A
+    LdDtVx(u8),             //Fx15
+    LdStVx(u8),             //Fx18
+    AddI(u8),               //Fx1E
+    LdF(u8),                //Fx29
+    LdB(u8),                //Fx33
+    Store(u8),              //Fx55
+    Load(u8),               //Fx65
+
+    //SUPER-CHIP
+    ScrollDown(u8),         //00Cn
+    ScrollRight,            //00FB
+    ScrollLeft,             //00FC
+    Exit,                   //00FD
+    Low,                    //00FE
+    High,                   //00FF
+    LdHf(u8),               //Fx30
+    StoreFlags(u8),         //Fx75
+    LoadFlags(u8),          //Fx85
+
+    //XO-CHIP
+    ScrollUp(u8),           //00Dn
+    StoreRange(u8, u8),     //5xy2
+    LoadRange(u8, u8),      //5xy3
+    LdILong,                //F000 nnnn, the address is in the next word
+    Plane(u8),              //Fn01
+    Audio,                  //F002
+    Pitch(u8),              //Fx3A
+
+    Invalid(u16)
+}
+
+impl Instruction {
+
+    pub fn decode(opcode: u16) -> Self {
+        let x = ((opcode >> 8) & 0xF) as u8;
+        let y = ((opcode >> 4) & 0xF) as u8;
+        let n = (opcode & 0xF) as u8;
+        let kk = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+
+        match opcode >> 12 {
+            0x0 => match opcode {
+                0x00E0 => Self::Cls,
+                0x00EE => Self::Ret,
+                0x00FB => Self::ScrollRight,
+                0x00FC => Self::ScrollLeft,
+                0x00FD => Self::Exit,
+                0x00FE => Self::Low,
+                0x00FF => Self::High,
+                _ if opcode & 0xFFF0 == 0x00C0 => Self::ScrollDown(n),
+                _ if opcode & 0xFFF0 == 0x00D0 => Self::ScrollUp(n),
+                _ => Self::Sys(nnn)
+            },
+            0x1 => Self::Jp(nnn),
+            0x2 => Self::Call(nnn),
+            0x3 => Self::SeByte(x, kk),
+            0x4 => Self::SneByte(x, kk),
+            0x5 => match n {
+                0x0 => Self::SeReg(x, y),
+                0x2 => Self::StoreRange(x, y),
+                0x3 => Self::LoadRange(x, y),
+                _ => Self::Invalid(opcode)
+            },
+            0x6 => Self::LdByte(x, kk),
+            0x7 => Self::AddByte(x, kk),
+            0x8 => match n {
+                0x0 => Self::LdReg(x, y),
+                0x1 => Self::Or(x, y),
+                0x2 => Self::And(x, y),
+                0x3 => Self::Xor(x, y),
+                0x4 => Self::AddReg(x, y),
+                0x5 => Self::Sub(x, y),
+                0x6 => Self::Shr(x, y),
+                0x7 => Self::Subn(x, y),
+                0xE => Self::Shl(x, y),
+                _ => Self::Invalid(opcode)
+            },
+            0x9 if n == 0 => Self::SneReg(x, y),
+            0xA => Self::LdI(nnn),
+            0xB => Self::JpV0(nnn),
+            0xC => Self::Rnd(x, kk),
+            0xD => Self::Drw(x, y, n),
+            0xE => match kk {
+                0x9E => Self::Skp(x),
+                0xA1 => Self::Sknp(x),
+                _ => Self::Invalid(opcode)
+            },
+            0xF => match kk {
+                0x00 if x == 0 => Self::LdILong,
+                0x01 => Self::Plane(x),
+                0x02 if x == 0 => Self::Audio,
+                0x07 => Self::LdVxDt(x),
+                0x0A => Self::LdVxK(x),
+                0x15 => Self::LdDtVx(x),
+                0x18 => Self::LdStVx(x),
+                0x1E => Self::AddI(x),
+                0x29 => Self::LdF(x),
+                0x30 => Self::LdHf(x),
+                0x33 => Self::LdB(x),
+                0x3A => Self::Pitch(x),
+                0x55 => Self::Store(x),
+                0x65 => Self::Load(x),
+                0x75 => Self::StoreFlags(x),
+                0x85 => Self::LoadFlags(x),
+                _ => Self::Invalid(opcode)
+            },
+            _ => Self::Invalid(opcode)
+        }
+    }
+
+    /// Size in bytes, F000 carries a 16 bit address after the opcode.
+    pub fn get_length(&self) -> u16 {
+        match self {
+            Self::LdILong => 4,
+            _ => 2
+        }
+    }
+
+    /// The first platform that has this instruction. Dxy0 draws a 16x16 sprite from SUPER-CHIP on,
+    /// on CHIP-8 it draws nothing.
+    pub fn get_platform(&self) -> Platform {
+        match self {
+            Self::ScrollDown(_) | Self::ScrollRight | Self::ScrollLeft | Self::Exit | Self::Low | Self::High |
+            Self::LdHf(_) | Self::StoreFlags(_) | Self::LoadFlags(_) | Self::Drw(_, _, 0) => Platform::SuperChip,
+            Self::ScrollUp(_) | Self::StoreRange(_, _) | Self::LoadRange(_, _) | Self::LdILong |
+            Self::Plane(_) | Self::Audio | Self::Pitch(_) => Platform::XoChip,
+            _ => Platform::Chip8
+        }
+    }
+
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Sys(nnn) => write!(f, "SYS {:X}", nnn),
+            Self::Cls => write!(f, "CLS"),
+            Self::Ret => write!(f, "RET"),
+            Self::Jp(nnn) => write!(f, "JP {:X}", nnn),
+            Self::Call(nnn) => write!(f, "CALL {:X}", nnn),
+            Self::SeByte(x, kk) => write!(f, "SE V{:X}, {:X}", x, kk),
+            Self::SneByte(x, kk) => write!(f, "SNE V{:X}, {:X}", x, kk),
+            Self::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Self::LdByte(x, kk) => write!(f, "LD V{:X}, {:X}", x, kk),
+            Self::AddByte(x, kk) => write!(f, "ADD V{:X}, {:X}", x, kk),
+            Self::LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Self::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Self::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Self::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Self::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Self::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Self::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Self::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Self::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Self::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Self::LdI(nnn) => write!(f, "LD I, {:X}", nnn),
+            Self::JpV0(nnn) => write!(f, "JP V0, {:X}", nnn),
+            Self::Rnd(x, kk) => write!(f, "RND V{:X}, {:X}", x, kk),
+            Self::Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {:X}", x, y, n),
+            Self::Skp(x) => write!(f, "SKP V{:X}", x),
+            Self::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Self::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Self::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Self::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Self::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Self::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Self::LdF(x) => write!(f, "LD F, V{:X}", x),
+            Self::LdB(x) => write!(f, "LD B, V{:X}", x),
+            Self::Store(x) => write!(f, "LD [I], V{:X}", x),
+            Self::Load(x) => write!(f, "LD V{:X}, [I]", x),
+            Self::ScrollDown(n) => write!(f, "SCD {:X}", n),
+            Self::ScrollRight => write!(f, "SCR"),
+            Self::ScrollLeft => write!(f, "SCL"),
+            Self::Exit => write!(f, "EXIT"),
+            Self::Low => write!(f, "LOW"),
+            Self::High => write!(f, "HIGH"),
+            Self::LdHf(x) => write!(f, "LD HF, V{:X}", x),
+            Self::StoreFlags(x) => write!(f, "LD R, V{:X}", x),
+            Self::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+            Self::ScrollUp(n) => write!(f, "SCU {:X}", n),
+            Self::StoreRange(x, y) => write!(f, "SAVE V{:X}-V{:X}", x, y),
+            Self::LoadRange(x, y) => write!(f, "LOAD V{:X}-V{:X}", x, y),
+            Self::LdILong => write!(f, "LD I, LONG"),
+            Self::Plane(n) => write!(f, "PLANE {:X}", n),
+            Self::Audio => write!(f, "AUDIO"),
+            Self::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Self::Invalid(opcode) => write!(f, "DW {:04X}", opcode)
+        }
+    }
+}
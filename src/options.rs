@@ -0,0 +1,256 @@
+use crate::clock_module::ClockRate;
+use crate::prng::RngMode;
+use crate::font::Font;
+use crate::memory_map::{MemoryMap, PROGRAM_START};
+use crate::platform::Platform;
+use crate::sys_call::SysMode;
+use crate::quirks::{KeyWaitQuirk, Quirks};
+use crate::rom_watcher::WatchMode;
+
+const DEFAULT_CPU_CLOCK: u64 = 1000;
+const DEFAULT_TURBO_MULTIPLIER: u32 = 4;
+
+const USAGE: &str = "usage: c8emu <rom> [clock_hz] [--ipf <instructions per frame>] [--turbo <multiplier, 0 for uncapped>] [--seed <n>] [--rng <xorshift|vip>] [--record <movie>] [--replay <movie>] [--key-wait <press|release>] [--platform <chip8|schip|xochip|chip8x|chip8e|chip10>] [--font <standard|vip|dream6800|eti660|schip|file>] [--font-base <hex address>] [--memory-map <flat|vip>] [--stack-in-memory] [--display-in-memory] [--sys <ignore|halt|error>] [--sys-handler <hex address>=<nop|background>] [--gamepad-map <file>] [--keypad] [--memory-viewer] [--sprite-viewer] [--watch [reset|keep|<save slot>]] [--gdb <port>] [--control <port>] [--script <file>] [--analyze] [--coverage <report file>] [--profile <folded stacks file>] [--symbols <file>] [--trace <file>]";
+
+pub struct Options {
+    pub filename: String,
+    pub clock_rate: ClockRate,
+    pub turbo_multiplier: u32,
+    pub seed: u64,
+    pub rng_mode: RngMode,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub quirks: Quirks,
+    pub quirks_given: bool, //a quirk was set on the command line, don't replace them with the detected platform's
+    pub platform: Option<Platform>, //overrides the detected platform
+    pub font: Font,
+    pub font_base: u16,
+    pub memory_map: MemoryMap,
+    pub sys_mode: SysMode,
+    pub sys_handlers: Vec<(u16, String)>,
+    pub gamepad_map: Option<String>,
+    pub keypad: bool,
+    pub memory_viewer: bool,
+    pub sprite_viewer: bool,
+    pub watch: Option<WatchMode>,
+    pub gdb_port: Option<u16>,
+    pub control_port: Option<u16>,
+    pub script: Option<String>,
+    pub analyze: bool,
+    pub coverage: Option<String>,
+    pub profile: Option<String>,
+    pub symbols: Option<String>,
+    pub trace: Option<String>
+}
+
+impl Options {
+
+    /// Parse command-line arguments.
+    ///
+    /// The first positional argument is the ROM, an optional second positional argument is the
+    /// cpu clock in Hz. `--ipf n` runs a fixed number of instructions per 60Hz frame instead.
+    /// `--turbo n` sets the fast forward speed, 0 runs as fast as the host allows.
+    /// `--seed n` fixes the CXkk random seed (random if not given), `--rng` picks the generator.
+    /// `--record file` writes input to a movie file, `--replay file` plays one back.
+    /// `--key-wait` picks whether Fx0A completes on key press or on release.
+    /// `--platform` runs as a platform instead of the detected one, needed for the CHIP-8X, CHIP-8E
+    /// and CHIP-10 hybrids since their ROMs can't be told apart from CHIP-8 ones.
+    /// `--font` picks a built-in font or loads one from a file, `--font-base` moves it from address 000,
+    /// it has to end below the program at 200.
+    /// `--memory-map vip` reserves the VIP interpreter's work area at EA0-FFF, `--stack-in-memory` and
+    /// `--display-in-memory` keep the stack and display there as the VIP does (and imply `--memory-map vip`).
+    /// `--sys` picks what 0nnn machine code calls do (ignored by default), `--sys-handler` stands in
+    /// a built-in routine for the machine code at an address and can be given more than once.
+    /// `--gamepad-map file` replaces the default gamepad bindings (needs the `gamepad` feature).
+    /// `--keypad` shows a clickable hex keypad next to the game.
+    /// `--memory-viewer` shows an editable hex view of memory next to the game.
+    /// `--sprite-viewer` shows memory from I onwards decoded as sprites next to the game.
+    /// `--watch mode` reloads the ROM when it changes on disk: `reset` (the default) restarts it, `keep`
+    /// keeps the registers and RAM running and a slot number restores that save state on top of the new ROM.
+    /// `--gdb port` listens for a GDB remote debugger on localhost.
+    /// `--control port` listens for JSON-RPC automation clients on localhost.
+    /// `--script file` attaches a Rhai script to emulator events (needs the `scripting` feature).
+    /// `--analyze` prints the platform and quirks the ROM looks like it needs, then exits.
+    /// `--coverage file` writes a coverage report on exit, lcov format if the name ends in `.info`.
+    /// `--profile file` writes cycles per call stack for flamegraph tools on exit and prints a summary.
+    /// `--symbols file` loads assembler labels for naming addresses in backtraces.
+    /// `--trace file` writes a binary trace of every executed instruction, for comparing with `c8trace`.
+    pub fn from_args(args: &[String]) -> Self {
+
+        let mut filename: Option<String> = None;
+        let mut clock_rate = ClockRate::Hz(DEFAULT_CPU_CLOCK);
+        let mut turbo_multiplier = DEFAULT_TURBO_MULTIPLIER;
+        let mut seed: u64 = rand::random();
+        let mut rng_mode = RngMode::Xorshift;
+        let mut record = None;
+        let mut replay = None;
+        let mut quirks = Quirks::default();
+        let mut quirks_given = false;
+        let mut platform = None;
+        let mut font = None;
+        let mut font_base = 0;
+        let mut vip_map = false;
+        let mut stack_in_memory = false;
+        let mut display_in_memory = false;
+        let mut sys_mode = SysMode::Ignore;
+        let mut sys_handlers = Vec::new();
+        let mut gamepad_map = None;
+        let mut keypad = false;
+        let mut memory_viewer = false;
+        let mut sprite_viewer = false;
+        let mut watch = None;
+        let mut gdb_port = None;
+        let mut control_port = None;
+        let mut script = None;
+        let mut analyze = false;
+        let mut coverage = None;
+        let mut profile = None;
+        let mut symbols = None;
+        let mut trace = None;
+
+        let mut iter = args.iter().skip(1).peekable();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--ipf" => {
+                    let ipf = Self::next_value(&mut iter, arg);
+                    clock_rate = ClockRate::InstructionsPerFrame(ipf.parse().expect("Error with command-line arguments"));
+                }
+                "--turbo" => {
+                    let turbo = Self::next_value(&mut iter, arg);
+                    turbo_multiplier = turbo.parse().expect("Error with command-line arguments");
+                }
+                "--seed" => {
+                    let seed_arg = Self::next_value(&mut iter, arg);
+                    seed = seed_arg.parse().expect("Error with command-line arguments");
+                }
+                "--rng" => {
+                    rng_mode = match Self::next_value(&mut iter, arg).as_str() {
+                        "xorshift" => RngMode::Xorshift,
+                        "vip" => RngMode::Vip,
+                        other => panic!("unknown rng mode {}\n{}", other, USAGE)
+                    };
+                }
+                "--record" => record = Some(Self::next_value(&mut iter, arg).clone()),
+                "--replay" => replay = Some(Self::next_value(&mut iter, arg).clone()),
+                "--gamepad-map" => gamepad_map = Some(Self::next_value(&mut iter, arg).clone()),
+                "--coverage" => coverage = Some(Self::next_value(&mut iter, arg).clone()),
+                "--profile" => profile = Some(Self::next_value(&mut iter, arg).clone()),
+                "--symbols" => symbols = Some(Self::next_value(&mut iter, arg).clone()),
+                "--font" => font = Some(Self::next_value(&mut iter, arg).clone()),
+                "--font-base" => {
+                    let base = Self::next_value(&mut iter, arg);
+                    font_base = u16::from_str_radix(base.trim_start_matches("0x"), 16).expect("Error with command-line arguments");
+                }
+                "--memory-map" => {
+                    vip_map = match Self::next_value(&mut iter, arg).as_str() {
+                        "flat" => false,
+                        "vip" => true,
+                        other => panic!("unknown memory map {}\n{}", other, USAGE)
+                    };
+                }
+                "--stack-in-memory" => stack_in_memory = true,
+                "--display-in-memory" => display_in_memory = true,
+                "--platform" => {
+                    let name = Self::next_value(&mut iter, arg);
+                    platform = Some(Platform::from_name(name).unwrap_or_else(|| panic!("unknown platform {}\n{}", name, USAGE)));
+                }
+                "--sys" => {
+                    let mode = Self::next_value(&mut iter, arg);
+                    sys_mode = SysMode::from_name(mode).unwrap_or_else(|| panic!("unknown sys mode {}\n{}", mode, USAGE));
+                }
+                "--sys-handler" => {
+                    let value = Self::next_value(&mut iter, arg);
+                    let (address, name) = value.split_once('=').unwrap_or_else(|| panic!("expected address=handler for {}\n{}", arg, USAGE));
+                    let address = u16::from_str_radix(address.trim_start_matches("0x"), 16).expect("Error with command-line arguments");
+                    sys_handlers.push((address, name.to_string()));
+                }
+                "--trace" => trace = Some(Self::next_value(&mut iter, arg).clone()),
+                "--script" => script = Some(Self::next_value(&mut iter, arg).clone()),
+                "--keypad" => keypad = true,
+                "--memory-viewer" => memory_viewer = true,
+                "--sprite-viewer" => sprite_viewer = true,
+                "--analyze" => analyze = true,
+                "--watch" => {
+                    //the mode is optional, a bare --watch resets
+                    watch = Some(match iter.next_if(|value| !value.starts_with("--")).map(String::as_str) {
+                        None | Some("reset") => WatchMode::Reset,
+                        Some("keep") => WatchMode::KeepRegisters,
+                        Some(slot) => WatchMode::RestoreState(slot.parse().unwrap_or_else(|_| panic!("unknown watch mode {}\n{}", slot, USAGE)))
+                    });
+                }
+                "--gdb" => {
+                    let port = Self::next_value(&mut iter, arg);
+                    gdb_port = Some(port.parse().expect("Error with command-line arguments"));
+                }
+                "--control" => {
+                    let port = Self::next_value(&mut iter, arg);
+                    control_port = Some(port.parse().expect("Error with command-line arguments"));
+                }
+                "--key-wait" => {
+                    quirks.key_wait = match Self::next_value(&mut iter, arg).as_str() {
+                        "press" => KeyWaitQuirk::Press,
+                        "release" => KeyWaitQuirk::Release,
+                        other => panic!("unknown key wait mode {}\n{}", other, USAGE)
+                    };
+                    quirks_given = true;
+                }
+                _ => {
+                    if filename.is_none() {
+                        filename = Some(arg.clone());
+                    } else {
+                        clock_rate = ClockRate::Hz(arg.parse().expect("Error with command-line arguments"));
+                    }
+                }
+            }
+        }
+
+        //the font has to end before the program does, a ROM loaded over it would garble both
+        let font = match font {
+            Some(name) => Font::from_name_or_file(&name).unwrap_or_else(|e| panic!("Error loading font {}: {}\n{}", name, e, USAGE)),
+            None => Font::default()
+        };
+        if font_base as usize + font.get_data().len() > PROGRAM_START as usize {
+            panic!("font at {:03X} runs past {:03X} into the program, --font-base has to leave it {} bytes\n{}", font_base, PROGRAM_START, font.get_data().len(), USAGE);
+        }
+
+        Self {
+            filename: filename.expect(USAGE),
+            clock_rate,
+            turbo_multiplier,
+            seed,
+            rng_mode,
+            record,
+            replay,
+            quirks,
+            quirks_given,
+            platform,
+            font,
+            font_base,
+            memory_map: if vip_map || stack_in_memory || display_in_memory {
+                MemoryMap::vip(stack_in_memory, display_in_memory)
+            } else {
+                MemoryMap::flat()
+            },
+            sys_mode,
+            sys_handlers,
+            gamepad_map,
+            keypad,
+            memory_viewer,
+            sprite_viewer,
+            watch,
+            gdb_port,
+            control_port,
+            script,
+            analyze,
+            coverage,
+            profile,
+            symbols,
+            trace
+        }
+    }
+
+    fn next_value<'a>(iter: &mut impl Iterator<Item = &'a String>, flag: &str) -> &'a String {
+        iter.next().unwrap_or_else(|| panic!("missing value for {}\n{}", flag, USAGE))
+    }
+
+}
@@ -0,0 +1,86 @@
+use crate::quirks::{KeyWaitQuirk, Quirks};
+use crate::memory_map::PROGRAM_START;
+
+/// CHIP-8 variants.
+///
+/// The first three extend each other in order, which the analyzer relies on when it picks the
+/// latest one a ROM needs. The rest are hybrids of the VIP interpreter that ROMs can't be
+/// detected as, they are only used when picked with `--platform`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+    Chip8X, //VP-590 color board: BxyN colors, 02A0 background, a second keypad
+    Chip8E, //extra skips, jumps, arithmetic and timer waits
+    Chip10  //128x64 display, otherwise CHIP-8
+}
+
+impl Platform {
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chip8" => Some(Platform::Chip8),
+            "schip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            "chip8x" => Some(Platform::Chip8X),
+            "chip8e" => Some(Platform::Chip8E),
+            "chip10" => Some(Platform::Chip10),
+            _ => None
+        }
+    }
+
+    /// Name as given to `--platform`, the inverse of `from_name`.
+    pub fn get_option_name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+            Platform::Chip8X => "chip8x",
+            Platform::Chip8E => "chip8e",
+            Platform::Chip10 => "chip10"
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+            Platform::Chip8X => "CHIP-8X",
+            Platform::Chip8E => "CHIP-8E",
+            Platform::Chip10 => "CHIP-10"
+        }
+    }
+
+    /// Quirks matching the interpreter ROMs for this platform were written against.
+    pub fn get_quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 | Platform::Chip8X | Platform::Chip8E | Platform::Chip10 => Quirks::default(), //all run on the VIP
+            Platform::SuperChip => Quirks { key_wait: KeyWaitQuirk::Press }, //the HP48 interpreter returns on key down
+            Platform::XoChip => Quirks { key_wait: KeyWaitQuirk::Release } //Octo waits for the release like the VIP
+        }
+    }
+
+    /// Address ROMs are loaded at and start running from, the CHIP-8X interpreter takes an extra page.
+    pub fn get_program_start(&self) -> u16 {
+        match self {
+            Platform::Chip8X => 0x300,
+            _ => PROGRAM_START
+        }
+    }
+
+    /// Display width and height in pixels.
+    pub fn get_display_size(&self) -> (u32, u32) {
+        match self {
+            Platform::Chip10 => (128, 64),
+            _ => (64, 32)
+        }
+    }
+
+    /// Whether the cpu runs this platform's instructions, rather than just the CHIP-8 ones.
+    pub fn is_emulated(&self) -> bool {
+        !matches!(self, Platform::SuperChip | Platform::XoChip)
+    }
+
+}
@@ -0,0 +1,102 @@
+use crate::save_state::{StateReader, StateWriter};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RngMode {
+    Xorshift, //seeded xorshift64*, reproducible for a given seed
+    Vip       //the COSMAC VIP routine, random bytes depend on the seed and the frame count
+}
+
+//page 0x0100 of the VIP CHIP-8 interpreter, transcribed from its listing. The random routine
+//indexes into its own code as a byte table, so the bytes are kept here rather than in guest memory.
+const VIP_INTERPRETER_PAGE: [u8; 256] = [
+    0x00, 0x00, 0x45, 0xA3, 0x98, 0x56, 0xD4, 0xF8, 0x81, 0xBC, 0xF8, 0x95, 0xAC, 0x22, 0xDC, 0x12,
+    0x56, 0xD4, 0x06, 0xB8, 0xD4, 0x06, 0xA8, 0xD4, 0x64, 0x0A, 0x01, 0xE6, 0x8A, 0xF4, 0xAA, 0x3B,
+    0x28, 0x9A, 0xFC, 0x01, 0xBA, 0xD4, 0xF8, 0x81, 0xBA, 0x06, 0xFA, 0x0F, 0xAA, 0x0A, 0xAA, 0xD4,
+    0xE6, 0x06, 0xBF, 0x93, 0xBE, 0xF8, 0x1B, 0xAE, 0x2A, 0x1A, 0xF8, 0x00, 0x5A, 0x0E, 0xF5, 0x3B,
+    0x4B, 0x56, 0x0A, 0xFC, 0x01, 0x5A, 0x30, 0x40, 0x4E, 0xF6, 0x3B, 0x3C, 0x9F, 0x56, 0x2A, 0x2A,
+    0xD4, 0x00, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x07, 0x5A, 0x87, 0xF3, 0x17, 0x1A, 0x3A, 0x5B,
+    0x12, 0xD4, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x0A, 0x57, 0x87, 0xF3, 0x17, 0x1A, 0x3A, 0x6B,
+    0x12, 0xD4, 0x15, 0x85, 0x22, 0x73, 0x95, 0x52, 0x25, 0x45, 0xA5, 0x86, 0xFA, 0x0F, 0xB5, 0xD4,
+    0x45, 0xE6, 0xF3, 0x3A, 0x82, 0x15, 0x15, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x88, 0xD4, 0x45, 0x07,
+    0x30, 0x8C, 0x45, 0x07, 0x30, 0x84, 0xE6, 0x62, 0x26, 0x45, 0xA3, 0x36, 0x88, 0xD4, 0x3E, 0x88,
+    0xD4, 0xF8, 0xF0, 0xA7, 0xE7, 0x45, 0xF4, 0xA5, 0x86, 0xFA, 0x0F, 0x3B, 0xB2, 0xFC, 0x01, 0xB5,
+    0xD4, 0x45, 0x56, 0xD4, 0x45, 0xE6, 0xF4, 0x56, 0xD4, 0x45, 0xFA, 0x0F, 0x3A, 0xC4, 0x07, 0x56,
+    0xD4, 0xAF, 0x22, 0xF8, 0xD3, 0x73, 0x8F, 0xF9, 0xF0, 0x52, 0xE6, 0x07, 0xD2, 0x56, 0xF8, 0xFF,
+    0xA6, 0xF8, 0x00, 0x7E, 0x56, 0xD4, 0x19, 0x89, 0xAE, 0x93, 0xBE, 0x99, 0xEE, 0xF4, 0x56, 0x76,
+    0xE6, 0xF4, 0xB9, 0x56, 0x45, 0xF2, 0x56, 0xD4, 0x45, 0xAA, 0x86, 0xFA, 0x0F, 0xBA, 0xD4, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x4B,
+];
+
+/// Seedable random number generator for CXkk.
+///
+/// The generator only depends on its seed (and in VIP mode, on the frame count) so a run can be
+/// reproduced exactly by passing the same seed.
+pub struct Prng {
+    mode: RngMode,
+    seed: u64,
+    state: u64,
+    vip_r9: u16 //R9 on the VIP, the high byte holds the last random byte
+}
+
+impl Prng {
+
+    pub fn new(mode: RngMode, seed: u64) -> Self {
+        Self {
+            mode,
+            seed,
+            state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed }, //xorshift gets stuck on 0
+            vip_r9: seed as u16
+        }
+    }
+
+    /// Restart the random sequence from the given seed.
+    pub fn reseed(&mut self, seed: u64) {
+        *self = Self::new(self.mode, seed);
+    }
+
+    pub fn get_seed(&self) -> u64 { self.seed }
+    pub fn get_mode(&self) -> RngMode { self.mode }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_bool(self.mode == RngMode::Vip);
+        writer.put_u64(self.seed);
+        writer.put_u64(self.state);
+        writer.put_u16(self.vip_r9);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.mode = if reader.get_bool()? { RngMode::Vip } else { RngMode::Xorshift };
+        self.seed = reader.get_u64()?;
+        self.state = reader.get_u64()?;
+        self.vip_r9 = reader.get_u16()?;
+        Ok(())
+    }
+
+    /// Called once per 60Hz frame, mirrors the VIP interrupt routine bumping R9.
+    pub fn tick_frame(&mut self) {
+        self.vip_r9 = self.vip_r9.wrapping_add(1);
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        match self.mode {
+            RngMode::Xorshift => {
+                self.state ^= self.state >> 12;
+                self.state ^= self.state << 25;
+                self.state ^= self.state >> 27;
+                (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+            }
+            RngMode::Vip => {
+                //INC R9, add the page byte R9.0 points at to R9.1, then add that sum shifted right
+                //through the carry back onto itself
+                self.vip_r9 = self.vip_r9.wrapping_add(1);
+                let page_byte = VIP_INTERPRETER_PAGE[(self.vip_r9 & 0xFF) as usize];
+                let (sum, carry) = ((self.vip_r9 >> 8) as u8).overflowing_add(page_byte);
+                let shifted = (sum >> 1) | ((carry as u8) << 7);
+                let rnd = sum.wrapping_add(shifted);
+                self.vip_r9 = (self.vip_r9 & 0x00FF) | ((rnd as u16) << 8);
+                rnd
+            }
+        }
+    }
+
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+
+use crate::opcode::Instruction;
+
+const UNKNOWN_ROUTINE: u16 = 0xFFFF; //stack entries from before the profiler saw the call, e.g. after loading a state
+const REPORT_LINES: usize = 10;
+
+/// Counts executed cycles per call stack, following CALL and RET.
+///
+/// Routines are named by their entry address, code outside any subroutine is `main`.
+pub struct Profiler {
+    stack: Vec<u16>,                  //entry addresses of the routines being run, innermost last
+    stacks: HashMap<Vec<u16>, u64>,   //cycles spent with each call stack
+    hot_pcs: Vec<u64>                 //cycles spent at each address
+}
+
+impl Profiler {
+
+    pub fn new() -> Self {
+        Self {
+            stack: Vec::new(),
+            stacks: HashMap::new(),
+            hot_pcs: vec![0; 0x10000]
+        }
+    }
+
+    /// Count an executed instruction, `sp` is the cpu's stack pointer after it ran.
+    pub fn record(&mut self, address: u16, opcode: u16, sp: u8) {
+        self.hot_pcs[address as usize] += 1;
+        match self.stacks.get_mut(&self.stack[..]) {
+            Some(cycles) => *cycles += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+
+        if let Instruction::Call(target) = Instruction::decode(opcode) {
+            self.stack.push(target);
+        }
+        //follow RET, and anything else that moved the stack pointer (resets, loaded states, debuggers)
+        self.stack.resize(sp as usize, UNKNOWN_ROUTINE);
+    }
+
+    /// Write the call stacks in the folded format flamegraph tools read, `main;sub_2A4;sub_31C 1234` per line.
+    pub fn write_folded(&self, filename: &str) -> std::io::Result<()> {
+        let mut lines: Vec<String> = self.stacks.iter()
+            .map(|(stack, cycles)| {
+                let frames: Vec<String> = std::iter::once("main".to_string()).chain(stack.iter().map(|entry| routine_name(*entry))).collect();
+                format!("{} {}", frames.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+        fs::write(filename, lines.join("\n") + "\n")
+    }
+
+    /// Self and inclusive cycles of the busiest routines, and the busiest addresses.
+    pub fn get_summary(&self) -> String {
+
+        let total: u64 = self.stacks.values().sum();
+        let percent = |cycles: u64| cycles as f64 * 100.0 / total.max(1) as f64;
+
+        //routine entry to (self, inclusive), main is None
+        let mut routines: HashMap<Option<u16>, (u64, u64)> = HashMap::new();
+        for (stack, cycles) in self.stacks.iter() {
+            let frames: Vec<Option<u16>> = std::iter::once(None).chain(stack.iter().map(|entry| Some(*entry))).collect();
+            routines.entry(*frames.last().unwrap_or(&None)).or_insert((0, 0)).0 += cycles;
+            //recursion would count the same cycles twice
+            let mut seen = Vec::new();
+            for frame in frames {
+                if !seen.contains(&frame) {
+                    routines.entry(frame).or_insert((0, 0)).1 += cycles;
+                    seen.push(frame);
+                }
+            }
+        }
+        let mut routines: Vec<(Option<u16>, (u64, u64))> = routines.into_iter().collect();
+        routines.sort_by_key(|(_, (self_cycles, inclusive))| std::cmp::Reverse((*inclusive, *self_cycles)));
+
+        let mut summary = String::new();
+        writeln!(summary, "{} cycles profiled", total).ok();
+        writeln!(summary, "{:<10} {:>20} {:>20}", "routine", "self", "inclusive").ok();
+        for (entry, (self_cycles, inclusive)) in routines.iter().take(REPORT_LINES) {
+            let name = entry.map(routine_name).unwrap_or_else(|| "main".to_string());
+            writeln!(summary, "{:<10} {:>12} {:>6.1}% {:>12} {:>6.1}%", name, self_cycles, percent(*self_cycles), inclusive, percent(*inclusive)).ok();
+        }
+
+        let mut hot_pcs: Vec<(usize, u64)> = self.hot_pcs.iter().copied().enumerate().filter(|(_, cycles)| *cycles > 0).collect();
+        hot_pcs.sort_by_key(|(address, cycles)| (std::cmp::Reverse(*cycles), *address));
+        writeln!(summary, "hot addresses").ok();
+        for (address, cycles) in hot_pcs.iter().take(REPORT_LINES) {
+            writeln!(summary, "{:03X} {:>12} {:>6.1}%", address, cycles, percent(*cycles)).ok();
+        }
+        summary
+    }
+
+}
+
+fn routine_name(entry: u16) -> String {
+    if entry == UNKNOWN_ROUTINE {
+        "unknown".to_string()
+    } else {
+        format!("sub_{:03X}", entry)
+    }
+}
@@ -0,0 +1,20 @@
+//! Behaviors that differ between CHIP-8 interpreters, defaults follow the original COSMAC VIP.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyWaitQuirk {
+    Press,  //Fx0A completes as soon as a key goes down
+    Release //Fx0A completes once the pressed key is let go again, like the VIP
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Quirks {
+    pub key_wait: KeyWaitQuirk
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            key_wait: KeyWaitQuirk::Release
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_DELAY: Duration = Duration::from_millis(250);
+
+/// What to do with the running program when the ROM file changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchMode {
+    Reset,          //reload and hard reset
+    KeepRegisters,  //copy the new ROM in but leave the rest of RAM, the cpu, display and timers as they are
+    RestoreState(u8) //reload and restore the given save slot on top of the new ROM
+}
+
+/// Watches the running ROM for changes by polling its modification time.
+///
+/// A change is only reported once the file has looked the same on two polls in a row,
+/// so an assembler that is halfway through writing the file doesn't trigger a reload.
+pub struct RomWatcher {
+    mode: WatchMode,
+    filename: String,
+    last_poll: Instant,
+    loaded: Option<(SystemTime, u64)>, //modification time and size of the ROM that is running
+    pending: Option<(SystemTime, u64)> //changed file seen on the last poll, waiting to settle
+}
+
+impl RomWatcher {
+
+    pub fn new(mode: WatchMode, filename: &str) -> Self {
+        Self {
+            mode,
+            filename: filename.to_string(),
+            last_poll: Instant::now(),
+            loaded: Self::stat(filename),
+            pending: None
+        }
+    }
+
+    pub fn get_mode(&self) -> WatchMode { self.mode }
+
+    /// Returns true when `filename` has changed on disk and should be reloaded.
+    /// Switching to a different file starts watching that one instead.
+    pub fn poll(&mut self, filename: &str, now: Instant) -> bool {
+
+        if filename != self.filename {
+            *self = Self::new(self.mode, filename);
+            return false;
+        }
+
+        if now.duration_since(self.last_poll) < POLL_DELAY {
+            return false;
+        }
+        self.last_poll = now;
+
+        let current = Self::stat(filename);
+        if current.is_none() || current == self.loaded {
+            self.pending = None;
+            return false;
+        }
+
+        if current == self.pending {
+            self.loaded = current;
+            self.pending = None;
+            true
+        } else {
+            self.pending = current;
+            false
+        }
+    }
+
+    fn stat(filename: &str) -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(filename).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use crate::platform::Platform;
+
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+const STATE_VERSION: u8 = 4;
+
+/// Serializes emulator components into a save state.
+///
+/// Each component writes its own fields in a fixed order with the put_* functions,
+/// and reads them back in the same order from a StateReader.
+pub struct StateWriter {
+    data: Vec<u8>
+}
+
+impl StateWriter {
+
+    pub fn new(rom_hash: u64, platform: Platform) -> Self {
+        let mut writer = Self { data: Vec::new() };
+        writer.put_bytes(STATE_MAGIC);
+        writer.put_u8(STATE_VERSION);
+        writer.put_u64(rom_hash);
+        let platform = platform.get_option_name().as_bytes();
+        writer.put_u8(platform.len() as u8);
+        writer.put_bytes(platform);
+        writer
+    }
+
+    pub fn put_u8(&mut self, val: u8) { self.data.push(val); }
+    pub fn put_u16(&mut self, val: u16) { self.data.extend_from_slice(&val.to_le_bytes()); }
+    pub fn put_u64(&mut self, val: u64) { self.data.extend_from_slice(&val.to_le_bytes()); }
+    pub fn put_bool(&mut self, val: bool) { self.put_u8(val as u8); }
+    pub fn put_bytes(&mut self, val: &[u8]) { self.data.extend_from_slice(val); }
+
+    pub fn write_file(&self, filename: &str) -> std::io::Result<()> {
+        fs::write(filename, &self.data)
+    }
+
+}
+
+pub struct StateReader {
+    data: Vec<u8>,
+    pos: usize
+}
+
+impl StateReader {
+
+    /// Open a save state file, checking that it was made from the ROM with the given hash on the given platform.
+    /// Pass None to accept a state from any ROM, e.g. an older build of the same program.
+    /// The platform has to match, it decides the display size and which components are saved.
+    pub fn read_file(filename: &str, rom_hash: Option<u64>, platform: Platform) -> std::io::Result<Self> {
+        let mut reader = Self { data: fs::read(filename)?, pos: 0 };
+
+        if reader.get_bytes(4)? != STATE_MAGIC || reader.get_u8()? != STATE_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "not a save state, or from an incompatible version"));
+        }
+        let state_hash = reader.get_u64()?;
+        if rom_hash.is_some_and(|hash| hash != state_hash) {
+            return Err(Error::new(ErrorKind::InvalidData, "save state belongs to a different ROM"));
+        }
+        let len = reader.get_u8()? as usize;
+        let state_platform = String::from_utf8_lossy(reader.get_bytes(len)?).to_string();
+        if state_platform != platform.get_option_name() {
+            let name = Platform::from_name(&state_platform).map_or("an unknown platform", |state_platform| state_platform.get_name());
+            return Err(Error::new(ErrorKind::InvalidData, format!("save state was made on {}, not {}", name, platform.get_name())));
+        }
+
+        Ok(reader)
+    }
+
+    pub fn get_bytes(&mut self, len: usize) -> std::io::Result<&[u8]> {
+        if self.pos + len > self.data.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "save state is truncated"));
+        }
+        self.pos += len;
+        Ok(&self.data[(self.pos - len)..self.pos])
+    }
+
+    pub fn get_u8(&mut self) -> std::io::Result<u8> {
+        Ok(self.get_bytes(1)?[0])
+    }
+
+    pub fn get_u16(&mut self) -> std::io::Result<u16> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.get_bytes(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn get_u64(&mut self) -> std::io::Result<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.get_bytes(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub fn get_bool(&mut self) -> std::io::Result<bool> {
+        Ok(self.get_u8()? != 0)
+    }
+
+}
+
+/// Save state file for a numbered slot, kept next to the ROM.
+pub fn slot_filename(rom_filename: &str, slot: u8) -> String {
+    format!("{}.state{}", rom_filename, slot)
+}
@@ -0,0 +1,279 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST};
+
+use crate::chip8::Chip8;
+use crate::input_source::VirtualDevice;
+use crate::text::{draw_text, fill_rect};
+
+const DEFAULT_COLOR: i64 = 0xFFFFFF;
+
+const HOOK_INSTRUCTION: &str = "on_instruction";
+const HOOK_FRAME: &str = "on_frame";
+const HOOK_MEMORY_WRITE: &str = "on_memory_write";
+const HOOK_KEY: &str = "on_key";
+
+enum OverlayItem {
+    Text { x: usize, y: usize, text: String, color: [u8; 4] },
+    Rect { x: usize, y: usize, width: usize, height: usize, color: [u8; 4] }
+}
+
+//copy of the machine state the script can see, written back after each hook. Memory is lent
+//for the duration of the hook rather than copied
+struct ScriptContext {
+    reg: [u8; 16],
+    i: u16,
+    pc: u16,
+    cycles: u64,
+    memory: Vec<u8>,
+    cpu_changed: bool,
+    pokes: Vec<(u16, u8)>,
+    overlay: Vec<OverlayItem>,
+    device: VirtualDevice,
+    keys_changed: bool
+}
+
+/// Rhai script attached to emulator events.
+///
+/// A script defines any of these functions, each is called when its event happens:
+///   `on_instruction()` before each instruction, `on_frame()` after each 60Hz frame,
+///   `on_memory_write(address, value)` after the cpu writes memory,
+///   `on_key(key, pressed)` when a key changes state.
+/// Hooks can call `reg(x)`, `set_reg(x, val)`, `reg_i()`, `set_i(val)`, `pc()`, `set_pc(val)`,
+/// `cycles()`, `peek(address)`, `poke(address, val)`, `press(key)`, `release(key)`,
+/// `draw_text(x, y, text [, color])` and `draw_rect(x, y, width, height [, color])`.
+/// Overlay drawing is cleared before each `on_frame`. Hooks share an object map as `this`,
+/// for keeping state between calls.
+pub struct ScriptHost {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Dynamic,
+    context: Rc<RefCell<ScriptContext>>,
+    hooks: Vec<&'static str>, //hooks the script defines, dropped if they fail
+    last_keys: [bool; 16]
+}
+
+impl ScriptHost {
+
+    pub fn load(filename: &str) -> Result<Self, Box<EvalAltResult>> {
+
+        let context = Rc::new(RefCell::new(ScriptContext {
+            reg: [0; 16],
+            i: 0,
+            pc: 0,
+            cycles: 0,
+            memory: Vec::new(),
+            cpu_changed: false,
+            pokes: Vec::new(),
+            overlay: Vec::new(),
+            device: VirtualDevice::new(),
+            keys_changed: false
+        }));
+
+        let mut engine = Engine::new();
+        register_api(&mut engine, &context);
+
+        let ast = engine.compile_file(PathBuf::from(filename))?;
+        let mut scope = Scope::new();
+        engine.run_ast_with_scope(&mut scope, &ast)?; //top level statements run once, at load
+
+        let hooks = [HOOK_INSTRUCTION, HOOK_FRAME, HOOK_MEMORY_WRITE, HOOK_KEY].iter()
+            .copied()
+            .filter(|hook| ast.iter_functions().any(|function| function.name == *hook))
+            .collect();
+
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            state: Dynamic::from(Map::new()),
+            context,
+            hooks,
+            last_keys: [false; 16]
+        })
+    }
+
+    /// Keys pressed by the script, add it to the keyboard module as an input source.
+    pub fn get_device(&self) -> VirtualDevice { self.context.borrow().device.clone() }
+
+    /// True if the script wants to hear about memory writes, the caller should turn on the memory write log.
+    pub fn wants_memory_writes(&self) -> bool { self.has_hook(HOOK_MEMORY_WRITE) }
+
+    /// Call before each instruction.
+    pub fn before_step(&mut self, chip8: &mut Chip8) {
+
+        if self.has_hook(HOOK_KEY) {
+            let keys = chip8.keyboard_module.get_keys();
+            for (key, (held, last)) in keys.iter().zip(self.last_keys).enumerate() {
+                if *held != last {
+                    self.call(chip8, HOOK_KEY, (key as i64, *held));
+                }
+            }
+            self.last_keys = keys;
+        }
+
+        if self.has_hook(HOOK_INSTRUCTION) {
+            self.call(chip8, HOOK_INSTRUCTION, ());
+        }
+    }
+
+    /// Call after each instruction.
+    pub fn after_step(&mut self, chip8: &mut Chip8) {
+        if self.has_hook(HOOK_MEMORY_WRITE) {
+            for address in chip8.memory.take_writes() {
+                if !self.has_hook(HOOK_MEMORY_WRITE) {
+                    break; //disabled by an error on an earlier write
+                }
+                let val = chip8.memory.get_memory(address);
+                self.call(chip8, HOOK_MEMORY_WRITE, (address as i64, val as i64));
+            }
+        }
+    }
+
+    /// Call after each frame's timer tick.
+    pub fn after_frame(&mut self, chip8: &mut Chip8) {
+        if self.has_hook(HOOK_FRAME) {
+            self.context.borrow_mut().overlay.clear();
+            self.call(chip8, HOOK_FRAME, ());
+        }
+    }
+
+    /// Draw the script's overlay over the frame.
+    pub fn draw(&self, frame: &mut [u8], frame_width: usize) {
+        for item in self.context.borrow().overlay.iter() {
+            match item {
+                OverlayItem::Text { x, y, text, color } => draw_text(frame, frame_width, frame_width, *x, *y, text, *color),
+                OverlayItem::Rect { x, y, width, height, color } => fill_rect(frame, frame_width, *x, *y, *width, *height, *color)
+            }
+        }
+    }
+
+    fn has_hook(&self, hook: &str) -> bool {
+        self.hooks.contains(&hook)
+    }
+
+    fn call(&mut self, chip8: &mut Chip8, hook: &'static str, args: impl FuncArgs) {
+
+        {
+            let mut context = self.context.borrow_mut();
+            for x in 0..16 {
+                context.reg[x] = chip8.cpu.get_reg(x);
+            }
+            context.i = chip8.cpu.get_i();
+            context.pc = chip8.cpu.get_pc();
+            context.cycles = chip8.cpu.get_cycles();
+            context.memory = chip8.memory.take_all(); //lent to the hook for peek and poke, not copied
+        }
+
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.state);
+        let result = self.engine.call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, hook, args);
+
+        //apply whatever the script changed
+        let mut context = self.context.borrow_mut();
+        chip8.memory.put_all(std::mem::take(&mut context.memory));
+        if let Err(e) = result {
+            println!("Script error in {}, disabling it: {}", hook, e);
+            self.hooks.retain(|h| *h != hook);
+            chip8.memory.set_write_log(self.wants_memory_writes());
+        }
+        if context.cpu_changed {
+            for x in 0..16 {
+                chip8.cpu.set_reg(x, context.reg[x]);
+            }
+            chip8.cpu.set_i(context.i);
+            chip8.cpu.set_pc(context.pc);
+            context.cpu_changed = false;
+        }
+        if !context.pokes.is_empty() {
+            for (address, val) in context.pokes.drain(..) {
+                chip8.memory.set_memory(address, val);
+            }
+            chip8.memory.take_writes(); //the script's own writes don't trigger on_memory_write
+        }
+        if context.keys_changed {
+            chip8.keyboard_module.poll_sources(&chip8.cpu);
+            context.keys_changed = false;
+        }
+    }
+
+}
+
+fn register_api(engine: &mut Engine, context: &Rc<RefCell<ScriptContext>>) {
+
+    let ctx = context.clone();
+    engine.register_fn("reg", move |x: i64| ctx.borrow().reg[(x & 0xF) as usize] as i64);
+    let ctx = context.clone();
+    engine.register_fn("set_reg", move |x: i64, val: i64| {
+        let mut context = ctx.borrow_mut();
+        context.reg[(x & 0xF) as usize] = val as u8;
+        context.cpu_changed = true;
+    });
+    let ctx = context.clone();
+    engine.register_fn("reg_i", move || ctx.borrow().i as i64);
+    let ctx = context.clone();
+    engine.register_fn("set_i", move |val: i64| {
+        let mut context = ctx.borrow_mut();
+        context.i = val as u16;
+        context.cpu_changed = true;
+    });
+    let ctx = context.clone();
+    engine.register_fn("pc", move || ctx.borrow().pc as i64);
+    let ctx = context.clone();
+    engine.register_fn("set_pc", move |val: i64| {
+        let mut context = ctx.borrow_mut();
+        context.pc = val as u16;
+        context.cpu_changed = true;
+    });
+    let ctx = context.clone();
+    engine.register_fn("cycles", move || ctx.borrow().cycles as i64);
+
+    let ctx = context.clone();
+    engine.register_fn("peek", move |address: i64| {
+        ctx.borrow().memory.get(address as usize).copied().unwrap_or(0) as i64
+    });
+    let ctx = context.clone();
+    engine.register_fn("poke", move |address: i64, val: i64| {
+        let mut context = ctx.borrow_mut();
+        if let Some(byte) = context.memory.get_mut(address as usize) {
+            *byte = val as u8;
+            context.pokes.push((address as u16, val as u8));
+        }
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("press", move |key: i64| {
+        let mut context = ctx.borrow_mut();
+        context.device.set_key((key & 0xF) as u8, true);
+        context.keys_changed = true;
+    });
+    let ctx = context.clone();
+    engine.register_fn("release", move |key: i64| {
+        let mut context = ctx.borrow_mut();
+        context.device.set_key((key & 0xF) as u8, false);
+        context.keys_changed = true;
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("draw_text", move |x: i64, y: i64, text: &str| {
+        ctx.borrow_mut().overlay.push(OverlayItem::Text { x: x.max(0) as usize, y: y.max(0) as usize, text: text.to_string(), color: to_color(DEFAULT_COLOR) });
+    });
+    let ctx = context.clone();
+    engine.register_fn("draw_text", move |x: i64, y: i64, text: &str, color: i64| {
+        ctx.borrow_mut().overlay.push(OverlayItem::Text { x: x.max(0) as usize, y: y.max(0) as usize, text: text.to_string(), color: to_color(color) });
+    });
+    let ctx = context.clone();
+    engine.register_fn("draw_rect", move |x: i64, y: i64, width: i64, height: i64| {
+        ctx.borrow_mut().overlay.push(OverlayItem::Rect { x: x.max(0) as usize, y: y.max(0) as usize, width: width.max(0) as usize, height: height.max(0) as usize, color: to_color(DEFAULT_COLOR) });
+    });
+    let ctx = context.clone();
+    engine.register_fn("draw_rect", move |x: i64, y: i64, width: i64, height: i64, color: i64| {
+        ctx.borrow_mut().overlay.push(OverlayItem::Rect { x: x.max(0) as usize, y: y.max(0) as usize, width: width.max(0) as usize, height: height.max(0) as usize, color: to_color(color) });
+    });
+}
+
+//0xRRGGBB to a pixels color
+fn to_color(color: i64) -> [u8; 4] {
+    [(color >> 16) as u8, (color >> 8) as u8, color as u8, 0xFF]
+}
@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::io::BufWriter;
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::text::{draw_text, fill_rect, LINE_HEIGHT};
+
+pub const SPRITE_VIEWER_WIDTH: u32 = 72;
+pub const SPRITE_VIEWER_HEIGHT: u32 = 60;
+
+const MODE_KEY: VirtualKeyCode = VirtualKeyCode::F6;           //switch between 8xN and 16x16 sprites
+const SHORTER_KEY: VirtualKeyCode = VirtualKeyCode::F7;
+const TALLER_KEY: VirtualKeyCode = VirtualKeyCode::F8;
+const FOLLOW_KEY: VirtualKeyCode = VirtualKeyCode::F9;         //go back to following I
+const EXPORT_KEY: VirtualKeyCode = VirtualKeyCode::F12;
+
+const DEFAULT_HEIGHT: usize = 8;
+const MAX_HEIGHT: usize = 15;   //Dxyn can't draw more than 15 rows
+const GAP: usize = 1;           //pixels between sprites
+
+const BACKGROUND: [u8; 4] = [0x10, 0x10, 0x10, 0xFF];
+const SPRITE_BACKGROUND: [u8; 4] = [0x30, 0x30, 0x30, 0xFF];
+const SPRITE_COLOR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const HEADER_COLOR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+
+/// Memory decoded as a grid of sprites, drawn to the right of the game.
+///
+/// Starts at I and follows it as the program runs, the mouse wheel over the panel steps through
+/// memory one sprite at a time instead. F6 switches between 8xN sprites and 16x16 SCHIP sprites,
+/// F7 and F8 change N, F9 follows I again and F12 writes the sprites shown to a PNG next to the ROM.
+pub struct SpriteViewer {
+    x_offset: usize,        //first pixel column of the panel in the frame
+    address: Option<u16>,   //first sprite shown, None to follow I
+    large: bool,            //16x16 instead of 8xN
+    height: usize           //N for 8xN sprites
+}
+
+impl SpriteViewer {
+
+    pub fn new(x_offset: u32) -> Self {
+        Self {
+            x_offset: x_offset as usize,
+            address: None,
+            large: false,
+            height: DEFAULT_HEIGHT
+        }
+    }
+
+    /// Handle the viewer's keys and scrolling. `pixel` is the cursor position in frame coordinates.
+    /// Returns true if the panel needs redrawing.
+    pub fn handle_input(&mut self, input: &WinitInputHelper, pixel: Option<(usize, usize)>, memory: &Memory, cpu: &Cpu, rom_filename: &str) -> bool {
+
+        let start = self.get_address(cpu);
+        let bytes_per_sprite = self.get_bytes_per_sprite() as i32;
+        let hovered = pixel.is_some_and(|(x, _)| (self.x_offset..self.x_offset + SPRITE_VIEWER_WIDTH as usize).contains(&x));
+
+        if hovered && input.scroll_diff() != 0.0 {
+            let step = if input.scroll_diff() > 0.0 { -bytes_per_sprite } else { bytes_per_sprite };
+            self.address = Some((start as i32 + step).clamp(0, memory.get_size() as i32 - 1) as u16);
+        } else if input.key_pressed(MODE_KEY) {
+            self.large = !self.large;
+        } else if input.key_pressed(SHORTER_KEY) && !self.large {
+            self.height = (self.height - 1).max(1);
+        } else if input.key_pressed(TALLER_KEY) && !self.large {
+            self.height = (self.height + 1).min(MAX_HEIGHT);
+        } else if input.key_pressed(FOLLOW_KEY) {
+            self.address = None;
+        } else if input.key_pressed(EXPORT_KEY) {
+            let filename = format!("{}.sprites-{:03X}.png", rom_filename, start);
+            match self.export(&filename, memory, start) {
+                Ok(()) => println!("Exported sprites to {}", filename),
+                Err(e) => println!("Error exporting sprites: {}", e)
+            }
+            return false;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn get_address(&self, cpu: &Cpu) -> u16 {
+        self.address.unwrap_or_else(|| cpu.get_i())
+    }
+
+    fn get_sprite_size(&self) -> (usize, usize) {
+        if self.large { (16, 16) } else { (8, self.height) }
+    }
+
+    fn get_bytes_per_sprite(&self) -> usize {
+        let (width, height) = self.get_sprite_size();
+        width / 8 * height
+    }
+
+    //sprites that fit across and down the panel, below the header line
+    fn get_grid(&self) -> (usize, usize) {
+        let (width, height) = self.get_sprite_size();
+        let columns = (SPRITE_VIEWER_WIDTH as usize + GAP) / (width + GAP);
+        let rows = (SPRITE_VIEWER_HEIGHT as usize - LINE_HEIGHT + GAP) / (height + GAP);
+        (columns, rows)
+    }
+
+    //whether pixel (x, y) of the sprite at `address` is set, memory past the end reads as blank
+    fn get_pixel(&self, memory: &Memory, address: usize, x: usize, y: usize) -> bool {
+        let (width, _) = self.get_sprite_size();
+        let byte_address = address + y * (width / 8) + x / 8;
+        byte_address < memory.get_size() && (memory.get_memory(byte_address as u16) >> (7 - x % 8)) & 1 != 0
+    }
+
+    /// Call `plot(x, y, set)` for every pixel of the grid of sprites starting at `start`,
+    /// with (0, 0) at the top-left of the first sprite.
+    fn for_each_pixel(&self, memory: &Memory, start: u16, mut plot: impl FnMut(usize, usize, bool)) {
+        let (width, height) = self.get_sprite_size();
+        let (columns, rows) = self.get_grid();
+        for n in 0..columns * rows {
+            let address = start as usize + n * self.get_bytes_per_sprite();
+            let (left, top) = ((n % columns) * (width + GAP), (n / columns) * (height + GAP));
+            for y in 0..height {
+                for x in 0..width {
+                    plot(left + x, top + y, self.get_pixel(memory, address, x, y));
+                }
+            }
+        }
+    }
+
+    /// Draw the panel into a frame that is `frame_width` pixels wide.
+    pub fn draw(&self, frame: &mut [u8], frame_width: usize, memory: &Memory, cpu: &Cpu) {
+
+        fill_rect(frame, frame_width, self.x_offset, 0, SPRITE_VIEWER_WIDTH as usize, SPRITE_VIEWER_HEIGHT as usize, BACKGROUND);
+
+        let start = self.get_address(cpu);
+        let (width, height) = self.get_sprite_size();
+        let header = match self.address {
+            Some(_) => format!("{:03X} {}X{}", start, width, height),
+            None => format!("I={:03X} {}X{}", start, width, height)
+        };
+        draw_text(frame, frame_width, self.x_offset + SPRITE_VIEWER_WIDTH as usize, self.x_offset + 1, 0, &header, HEADER_COLOR);
+
+        self.for_each_pixel(memory, start, |x, y, set| {
+            let index = ((LINE_HEIGHT + y) * frame_width + self.x_offset + x) * 4;
+            frame[index..index + 4].copy_from_slice(if set { &SPRITE_COLOR } else { &SPRITE_BACKGROUND });
+        });
+    }
+
+    /// Write the grid of sprites starting at `start` to a PNG, set pixels white and the rest transparent.
+    fn export(&self, filename: &str, memory: &Memory, start: u16) -> std::io::Result<()> {
+
+        let (width, height) = self.get_sprite_size();
+        let (columns, rows) = self.get_grid();
+        let image_width = columns * (width + GAP) - GAP;
+        let image_height = rows * (height + GAP) - GAP;
+
+        let mut image = vec![0; image_width * image_height * 4];
+        self.for_each_pixel(memory, start, |x, y, set| {
+            if set {
+                let index = (y * image_width + x) * 4;
+                image[index..index + 4].copy_from_slice(&SPRITE_COLOR);
+            }
+        });
+
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(filename)?), image_width as u32, image_height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&image)?;
+        Ok(())
+    }
+
+}
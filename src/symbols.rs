@@ -0,0 +1,57 @@
+use std::fs;
+
+/// Labels from an assembler's symbol file, for naming addresses in backtraces.
+///
+/// Each line holds a label and an address, either as `label = 0x2A4`, `label: $2A4` or `2A4 label`.
+/// An address written with `0x` or `$` is picked out wherever it is, otherwise the first hex
+/// number on the line is the address. Blank lines and lines starting with `#` or `;` are ignored.
+pub struct SymbolTable {
+    symbols: Vec<(u16, String)> //sorted by address
+}
+
+impl SymbolTable {
+
+    pub fn new() -> Self {
+        Self { symbols: Vec::new() }
+    }
+
+    pub fn load(filename: &str) -> std::io::Result<Self> {
+        let text = fs::read_to_string(filename)?;
+        let mut symbols: Vec<(u16, String)> = text.lines().filter_map(Self::parse_line).collect();
+        symbols.sort();
+        Ok(Self { symbols })
+    }
+
+    fn parse_line(line: &str) -> Option<(u16, String)> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            return None;
+        }
+        let tokens: Vec<&str> = line.split(|c: char| c.is_whitespace() || c == '=' || c == ':').filter(|token| !token.is_empty()).collect();
+        if tokens.len() != 2 {
+            return None;
+        }
+
+        let prefixed = |token: &str| token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")).or_else(|| token.strip_prefix('$')).map(str::to_string);
+        let address_index = tokens.iter().position(|token| prefixed(token).is_some())
+            .or_else(|| tokens.iter().position(|token| u16::from_str_radix(token, 16).is_ok()))?;
+        let digits = prefixed(tokens[address_index]).unwrap_or_else(|| tokens[address_index].to_string());
+        let address = u16::from_str_radix(&digits, 16).ok()?;
+
+        Some((address, tokens[1 - address_index].to_string()))
+    }
+
+    /// Name an address by the closest label at or before it, e.g. `draw_ship+4`.
+    /// Addresses before the first label are just given in hex.
+    pub fn resolve(&self, address: u16) -> String {
+        let index = self.symbols.partition_point(|(symbol_address, _)| *symbol_address <= address);
+        match index.checked_sub(1).map(|index| &self.symbols[index]) {
+            Some((symbol_address, name)) if *symbol_address == address => name.clone(),
+            Some((symbol_address, name)) => format!("{}+{}", name, address - symbol_address),
+            None => format!("{:03X}", address)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool { self.symbols.is_empty() }
+
+}
@@ -0,0 +1,65 @@
+//! 0nnn SYS calls, which jump into RCA 1802 machine code on the COSMAC VIP.
+//!
+//! The machine code can't be run, so a SYS call is ignored, halts the program or faults,
+//! unless a Rust handler that does the same job as the routine is registered for its address.
+
+use std::collections::HashMap;
+
+use crate::cpu::Cpu;
+use crate::display_module::DisplayModule;
+use crate::memory::Memory;
+
+/// What to do with a SYS call that has no handler.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SysMode {
+    Ignore, //carry on with the next instruction
+    Halt,   //stay on the call, like a program that ends by jumping to itself
+    Error   //fault, printing a backtrace
+}
+
+impl SysMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ignore" => Some(SysMode::Ignore),
+            "halt" => Some(SysMode::Halt),
+            "error" => Some(SysMode::Error),
+            _ => None
+        }
+    }
+}
+
+/// Stands in for a machine code routine, called in place of the SYS instruction with PC already past it.
+pub type SysHandler = fn(&mut Cpu, &mut Memory, &mut DisplayModule);
+
+/// Handlers by the address of the routine they stand in for.
+pub struct SysHandlers {
+    handlers: HashMap<u16, SysHandler>
+}
+
+impl SysHandlers {
+
+    pub fn new() -> Self {
+        Self { handlers: HashMap::new() }
+    }
+
+    pub fn register(&mut self, address: u16, handler: SysHandler) {
+        self.handlers.insert(address, handler);
+    }
+
+    pub fn get(&self, address: u16) -> Option<SysHandler> {
+        self.handlers.get(&address).copied()
+    }
+
+}
+
+/// Handlers for routines common enough to name, for `--sys-handler address=name`:
+/// `nop` skips a routine, e.g. one that drives hardware the emulator doesn't have, even when SYS calls are errors.
+/// `background` is the CHIP-8X interpreter's routine at 02A0, which steps the VP-590 color board's
+/// background through blue, black, green and red. `--platform chip8x` registers it there.
+pub fn builtin(name: &str) -> Option<SysHandler> {
+    match name {
+        "nop" => Some(|_, _, _| {}),
+        "background" => Some(|_, _, display_module| display_module.cycle_background()),
+        _ => None
+    }
+}
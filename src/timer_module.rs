@@ -1,4 +1,7 @@
+use crate::save_state::{StateReader, StateWriter};
 
+
+#[derive(Clone)]
 pub struct TimerModule {
     sound_timer: u8,
     delay_timer: u8,
@@ -19,6 +22,10 @@ impl TimerModule {
 
     }
 
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
     //to be called at 60hz
     pub fn update(&mut self) {
 
@@ -40,6 +47,7 @@ impl TimerModule {
 
     }
 
+    #[allow(dead_code)]
     pub fn get_sound_flag(&self) -> bool {
         self.sound_flag
     }
@@ -65,6 +73,19 @@ impl TimerModule {
         }
     }
 
-}
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_u8(self.sound_timer);
+        writer.put_u8(self.delay_timer);
+        writer.put_bool(self.sound_flag);
+        writer.put_bool(self.delay_flag);
+    }
 
+    pub fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.sound_timer = reader.get_u8()?;
+        self.delay_timer = reader.get_u8()?;
+        self.sound_flag = reader.get_bool()?;
+        self.delay_flag = reader.get_bool()?;
+        Ok(())
+    }
 
+}
@@ -0,0 +1,42 @@
+//! Binary execution trace, one fixed size record per executed instruction.
+//!
+//! The file starts with `C8TR` and a version byte, then each record holds the cpu state
+//! before the instruction ran: cycle (u64), PC, opcode and I (u16), SP (u8) and V0-VF,
+//! all little-endian. `c8trace` reads these files.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+pub const TRACE_MAGIC: &[u8; 4] = b"C8TR";
+pub const TRACE_VERSION: u8 = 1;
+pub const RECORD_SIZE: usize = 8 + 2 + 2 + 2 + 1 + 16;
+
+pub struct TraceWriter {
+    file: BufWriter<File>
+}
+
+impl TraceWriter {
+
+    pub fn create(filename: &str) -> std::io::Result<Self> {
+        let mut file = BufWriter::new(File::create(filename)?);
+        file.write_all(TRACE_MAGIC)?;
+        file.write_all(&[TRACE_VERSION])?;
+        Ok(Self { file })
+    }
+
+    pub fn record(&mut self, cycle: u64, pc: u16, opcode: u16, i: u16, sp: u8, reg: &[u8; 16]) -> std::io::Result<()> {
+        let mut record = [0; RECORD_SIZE];
+        record[0..8].copy_from_slice(&cycle.to_le_bytes());
+        record[8..10].copy_from_slice(&pc.to_le_bytes());
+        record[10..12].copy_from_slice(&opcode.to_le_bytes());
+        record[12..14].copy_from_slice(&i.to_le_bytes());
+        record[14] = sp;
+        record[15..31].copy_from_slice(reg);
+        self.file.write_all(&record)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+
+}